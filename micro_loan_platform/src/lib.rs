@@ -1,26 +1,484 @@
-use soroban_sdk::{contract, contractimpl, Env, Address, Vec, log, Bytes};
+use soroban_sdk::{contract, contractclient, contracterror, contractimpl, contracttype, symbol_short, token, Env, Address, BytesN, Map, String, Symbol, Vec, log, Bytes};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone)]
 #[contracttype]
 struct Loan {
+    id: u64,
     borrower: Address,
     amount: i64,
     interest_rate: f32,
     repaid_amount: i64,
     savings: i64,
-    is_active: bool,
+    status: LoanStatus,
+    accrued_interest: i64,
+    interest_paid: i64,
+    last_accrual_ts: u64,
+    originated_at: u64,
+    collateral: i64,
+    term_secs: u64,
+    capitalized_interest: i64,
+    base_rate: f32,
+    min_rate_bps: u32,
+    approved_at: u64,
+    denom: Address,
+    symbol: String,
+    blend_funded: i64,
+    promo_ends_at: u64,
+    accrued_fees: i64,
+    accruing: bool,
+    disburse_to: Option<Address>,
+    variable_rate: bool,
+    accrual_carry: i128,
+    period_interest: i64,
+    late_fee_installment: u64,
+}
+
+/// Large loans start `Pending` until enough underwriters approve them.
+/// With a disbursement delay configured, approved loans wait in
+/// `ReadyToDisburse` until `disburse` is called after the delay. A
+/// `Defaulted` loan stops accruing but can still be paid down, and is closed
+/// by `reinstate_loan` once nothing is owed.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+enum LoanStatus {
+    Pending,
+    ReadyToDisburse,
+    Active,
+    Repaid,
+    Defaulted,
 }
 
 #[derive(Serialize, Deserialize)]
 #[contracttype]
 struct LendingPool {
     total_funds: i64,
-    loans: Vec<Loan>,
+    loans: Map<u64, Loan>,
     insurance_fund: i64,
     base_interest_rate: f32,
+    insurance_funding_bps: u32,
+    next_loan_id: u64,
+    savings_bps: u32,
+    tapered_savings_bps: u32,
+    reward_threshold: i64,
+    token: Address,
+    min_repayment: i64,
+    cooldown_secs: u64,
+    last_origination: Map<Address, u64>,
+    first_origination: Map<Address, u64>,
+    max_utilization_bps: u32,
+    notification_prefs: Map<Address, u32>,
+    collateral_token: Option<Address>,
+    max_ltv_bps: u32,
+    interest_window_start: u64,
+    interest_window_earned: i64,
+    last_window_earned: i64,
+    last_window_secs: u64,
+    lenders: Map<Address, LenderPosition>,
+    total_shares: i64,
+    yield_per_share: i128,
+    listings: Map<u64, ShareListing>,
+    next_listing_id: u64,
+    grace_period_secs: u64,
+    penalty_multiplier_bps: u32,
+    repayment_keys: Map<Address, Vec<(BytesN<32>, u64)>>,
+    interest_insurance_bps: u32,
+    interest_yield_bps: u32,
+    credit_scores: Map<Address, u32>,
+    min_term_secs: u64,
+    max_term_secs: u64,
+    min_loan_amount: i64,
+    max_loan_amount: i64,
+    origination_fee_bps: u32,
+    reserve_bps: u32,
+    underwriters: Vec<Address>,
+    approval_quorum: u32,
+    large_loan_threshold: i64,
+    loan_approvals: Map<u64, Vec<Address>>,
+    queued_changes: Map<u64, QueuedChange>,
+    next_change_id: u64,
+    max_capitalization_bps: u32,
+    min_loan_rate_bps: u32,
+    early_repayment_rebate_bps: u32,
+    early_repayment_penalty_bps: u32,
+    disbursement_delay_secs: u64,
+    loan_events: Map<u64, Vec<LoanEvent>>,
+    rate_proposals: Map<u64, RateProposal>,
+    next_proposal_id: u64,
+    token_symbols: Map<Address, String>,
+    borrowed_totals: Map<Address, i64>,
+    savings_totals: Map<Address, i64>,
+    deposited_totals: Map<Address, i64>,
+    paused: bool,
+    max_outflow_per_window: i64,
+    outflow_window_secs: u64,
+    outflow_window_start: u64,
+    outflow_in_window: i64,
+    repaid_counts: Map<Address, u32>,
+    loyalty_discount_bps: u32,
+    max_loyalty_discount_bps: u32,
+    rounding_mode: RoundingMode,
+    blacklist: Vec<Address>,
+    blend_pool: Option<Address>,
+    auto_return_savings: Map<Address, bool>,
+    max_loans: u32,
+    max_active_loans: u32,
+    defaulted_at: Map<Address, u64>,
+    default_cooldown_secs: u64,
+    compounding_period_secs: u64,
+    max_savings_per_loan: i64,
+    fee_collector: Address,
+    insurance_from_savings: i64,
+    promo_until_ts: u64,
+    promo_grace_secs: u64,
+    late_fee_bps: u32,
+    max_late_fee_bps_of_principal: u32,
+    credit_bureau: Option<Address>,
+    min_collateral_bps: u32,
+    disabled_bands: Vec<u32>,
+    config_history: Vec<ConfigChange>,
+    payment_application: PaymentApplication,
+    version: u32,
+    variable_rate_loans: bool,
+    keeper: Option<Address>,
+}
+
+/// Snapshot of the pool's policy parameters, for clients to render.
+#[derive(Serialize, Deserialize, Clone)]
+#[contracttype]
+struct PoolConfig {
+    base_interest_rate: f32,
+    min_loan_amount: i64,
+    max_loan_amount: i64,
+    origination_fee_bps: u32,
+    reserve_bps: u32,
+    savings_bps: u32,
+    tapered_savings_bps: u32,
+    reward_threshold: i64,
+    insurance_funding_bps: u32,
+    interest_insurance_bps: u32,
+    interest_yield_bps: u32,
+    max_utilization_bps: u32,
+    max_ltv_bps: u32,
+    min_repayment: i64,
+    cooldown_secs: u64,
+    min_term_secs: u64,
+    max_term_secs: u64,
+    grace_period_secs: u64,
+    penalty_multiplier_bps: u32,
+    min_loan_rate_bps: u32,
+    early_repayment_rebate_bps: u32,
+    early_repayment_penalty_bps: u32,
+    disbursement_delay_secs: u64,
+    max_outflow_per_window: i64,
+    outflow_window_secs: u64,
+    loyalty_discount_bps: u32,
+    max_loyalty_discount_bps: u32,
+    rounding_mode: RoundingMode,
+    max_loans: u32,
+    max_active_loans: u32,
+    default_cooldown_secs: u64,
+    compounding_period_secs: u64,
+    max_savings_per_loan: i64,
+    promo_until_ts: u64,
+    promo_grace_secs: u64,
+    late_fee_bps: u32,
+    max_late_fee_bps_of_principal: u32,
+    min_collateral_bps: u32,
+    payment_application: PaymentApplication,
+    variable_rate_loans: bool,
+}
+
+/// How interest, origination fees and savings are rounded. `Down` truncates,
+/// which favors borrowers; `Up` favors the pool and its lenders; `Nearest`
+/// is neutral on average.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+enum RoundingMode {
+    Down,
+    Up,
+    Nearest,
+}
+
+/// Which part of the balance a repayment covers first: interest owed, or the
+/// outstanding principal.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+enum PaymentApplication {
+    InterestFirst,
+    PrincipalFirst,
+}
+
+/// A lender's pool shares and yield bookkeeping. Shares are minted 1:1 with
+/// deposits; interest repaid is distributed per share via `yield_per_share`.
+#[derive(Serialize, Deserialize, Clone)]
+#[contracttype]
+struct LenderPosition {
+    shares: i64,
+    reward_debt: i128,
+    unclaimed: i64,
+    listed: i64,
+}
+
+/// Shares offered for sale at a fixed total `price` in the lending token.
+/// Listed shares stay with the seller (and keep earning) until bought.
+#[derive(Serialize, Deserialize, Clone)]
+#[contracttype]
+struct ShareListing {
+    seller: Address,
+    amount: i64,
+    price: i64,
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum LoanError {
+    Unauthorized = 1,
+    InvalidAmount = 2,
+    InvalidParameter = 3,
+    AmountOutOfRange = 4,
+    InvalidTerm = 5,
+    InsufficientFunds = 6,
+    BlendPoolUnavailable = 7,
+    UtilizationCapExceeded = 8,
+    BorrowerInCooldown = 9,
+    LoanNotFound = 10,
+    RepaymentBelowMinimum = 11,
+    ProtectedToken = 12,
+    CollateralDisabled = 13,
+    CollateralHeld = 14,
+    InsufficientShares = 15,
+    SelfTransfer = 16,
+    ListingNotFound = 17,
+    ReserveBreached = 18,
+    NotUnderwriter = 19,
+    AlreadyApproved = 20,
+    LoanNotPending = 21,
+    ChangeNotFound = 22,
+    TimelockActive = 23,
+    LoanCurrent = 24,
+    CapitalizationCapExceeded = 25,
+    DisbursementDelayActive = 26,
+    ProposalNotFound = 27,
+    AlreadyVoted = 28,
+    VotingClosed = 29,
+    VotingOpen = 30,
+    LendingPaused = 31,
+    BorrowerBlacklisted = 32,
+    LoanNotDefaulted = 33,
+    BalanceOutstanding = 34,
+    BlendBalanceOutstanding = 35,
+    LoanNotRepaid = 36,
+    LoanCapReached = 37,
+    DefaultCooldownActive = 38,
+    LoansOutstanding = 39,
+    TokenBalanceRemaining = 40,
+    RetentionPeriodActive = 41,
+    InsufficientCollateral = 42,
+    BandLendingPaused = 43,
+    IdCollision = 44,
+    InsufficientSavingsHistory = 45,
+    RateNotImproved = 46,
+    ActiveLoanCapReached = 47,
+    NothingToCapitalize = 48,
+    LateFeeAlreadyCharged = 49,
+}
+
+/// An external credit registry. It may know a borrower's score from other
+/// lenders, and is told how loans here end.
+#[contractclient(name = "CreditBureauClient")]
+pub trait CreditBureau {
+    fn score(env: Env, borrower: Address) -> Option<u32>;
+    fn report(env: Env, borrower: Address, loan_id: u64, defaulted: bool);
+}
+
+/// The slice of a Blend lending pool this contract draws on when its own
+/// idle funds can't cover a loan.
+#[contractclient(name = "BlendClient")]
+pub trait BlendPool {
+    fn available(env: Env) -> i64;
+    fn draw(env: Env, to: Address, amount: i64);
+    fn repay(env: Env, from: Address, amount: i64);
+}
+
+/// A sensitive parameter update. These can only be applied through
+/// `queue_param_change` / `execute_param_change`, after `PARAM_TIMELOCK_SECS`.
+#[derive(Serialize, Deserialize, Clone)]
+#[contracttype]
+enum ParamChange {
+    LoanLimits(i64, i64),
+    OriginationFeeBps(u32),
+    ReserveBps(u32),
+    CollateralToken(Address),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[contracttype]
+struct QueuedChange {
+    change: ParamChange,
+    execute_at: u64,
+}
+
+/// A lender proposal to set `base_interest_rate` to `new_bps`. Votes are
+/// weighted by the voter's shares when the proposal was opened, as recorded
+/// in `weights`, so shares moved mid-vote can't be voted twice.
+#[derive(Serialize, Deserialize, Clone)]
+#[contracttype]
+struct RateProposal {
+    new_bps: u32,
+    votes_for: i64,
+    votes_against: i64,
+    voters: Vec<Address>,
+    ends_at: u64,
+    weights: Map<Address, i64>,
+    total_weight: i64,
+}
+
+/// A parameter value in the config history. Rates and fees are recorded
+/// in bps; `Unset` stands for an optional address that isn't configured.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[contracttype]
+enum ConfigValue {
+    Num(i128),
+    Addr(Address),
+    Flag(bool),
+    Unset,
+}
+
+impl From<Option<Address>> for ConfigValue {
+    fn from(addr: Option<Address>) -> Self {
+        match addr {
+            Some(addr) => ConfigValue::Addr(addr),
+            None => ConfigValue::Unset,
+        }
+    }
+}
+
+/// One owner or governance parameter change, see `get_config_history`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[contracttype]
+struct ConfigChange {
+    ts: u64,
+    param: Symbol,
+    old: ConfigValue,
+    new: ConfigValue,
+    by: Address,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+enum LoanEventKind {
+    Originated,
+    Repayment,
+    Capitalized,
+    Closed,
+    Defaulted,
+    Reinstated,
+}
+
+/// An entry in a loan's stored history, see `get_loan_events`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[contracttype]
+struct LoanEvent {
+    ts: u64,
+    kind: LoanEventKind,
+    amount: i64,
+}
+
+/// One-screen summary of a loan for wallets, as of the current ledger time.
+#[derive(Serialize, Deserialize, Clone)]
+#[contracttype]
+struct Statement {
+    originated_at: u64,
+    amount: i64,
+    interest_rate: f32,
+    interest_accrued: i64,
+    total_repaid: i64,
+    savings: i64,
+    remaining_balance: i64,
+    status: LoanStatus,
+    denom: Address,
+    symbol: String,
+}
+
+/// How a single repayment is divided up, with interest accrued up to the
+/// time of the repayment. The part of the payment set aside as savings is
+/// `to_savings + to_insurance`: the borrower is credited `to_savings` and the
+/// insurance fund gets `to_insurance`, so savings, insurance and the pool
+/// exactly partition each payment.
+struct RepaymentSplit {
+    to_principal: i64,
+    to_interest: i64,
+    to_savings: i64,
+    to_insurance: i64,
+    interest_to_insurance: i64,
+    interest_to_yield: i64,
+}
+
+/// What `open_loan` is opening: a loan asked for through `request_loan`, at
+/// the borrower's offered rate and secured to the pool's collateral minimum,
+/// or an unsecured savings advance at a fixed rate.
+enum LoanOffer {
+    Regular { term_secs: u64, collateral: i64, disburse_to: Option<Address> },
+    Advance { rate: f32 },
 }
 
+const BPS_DENOMINATOR: i64 = 10_000;
+const SECONDS_PER_YEAR: u64 = 31_536_000;
+const SECONDS_PER_DAY: u64 = 86_400;
+const APY_WINDOW_SECS: u64 = 30 * 24 * 60 * 60;
+const YIELD_PRECISION: i128 = 1_000_000_000_000;
+const INSTALLMENT_PERIOD_SECS: u64 = 30 * 24 * 60 * 60;
+const IDEMPOTENCY_RETENTION_SECS: u64 = 24 * 60 * 60;
+const MAX_IDEMPOTENCY_KEYS: u32 = 16;
+const PARAM_TIMELOCK_SECS: u64 = 2 * 24 * 60 * 60;
+const MAX_LOAN_EVENTS: u32 = 32;
+/// Most recent parameter changes kept in `config_history`.
+const MAX_CONFIG_HISTORY: u32 = 64;
+/// Utilization at which the pricing premium maxes out at 5 points.
+const UTILIZATION_CAP_BPS: u32 = 25_000;
+/// How long a closed or defaulted loan is kept before it can be archived.
+const ARCHIVE_RETENTION_SECS: u64 = 180 * 24 * 60 * 60;
+const MAX_LEADERBOARD_SIZE: u32 = 50;
+/// Shortest compounding period `set_compounding_period_secs` accepts.
+const MIN_COMPOUNDING_PERIOD_SECS: u64 = 24 * 60 * 60;
+const VOTING_PERIOD_SECS: u64 = 3 * 24 * 60 * 60;
+/// Savings advances: a one-installment loan at the base rate, for borrowers
+/// saving at least `ADVANCE_MIN_SAVINGS_BPS` of the advance per month.
+const ADVANCE_TERM_SECS: u64 = 30 * 24 * 60 * 60;
+const ADVANCE_MIN_SAVINGS_BPS: i64 = 2_000;
+/// Share of all pool shares that must vote for a rate proposal to count.
+const VOTE_QUORUM_BPS: i64 = 2_000;
+
+const DEFAULT_CREDIT_SCORE: u32 = 600;
+const MAX_CREDIT_SCORE: u32 = 850;
+/// Credit score lost on default, and the part given back on reinstatement.
+const DEFAULT_SCORE_PENALTY: u32 = 200;
+const REINSTATE_SCORE_RECOVERY: u32 = 100;
+/// Lower bound of each credit-score band used for portfolio reporting.
+const CREDIT_BANDS: [u32; 3] = [0, 500, 700];
+/// Config history names for `set_band_lending`, one per `CREDIT_BANDS` entry.
+const CREDIT_BAND_PARAMS: [&str; 3] = ["band_0", "band_500", "band_700"];
+/// Lower bound, in days past due, of each `aging_report` bucket: current,
+/// 1-30, 31-60 and over 60.
+const AGING_BUCKETS: [u32; 4] = [0, 1, 31, 61];
+/// Percentage points added to the pool rate for borrowers in each band.
+const CREDIT_BAND_SPREADS: [f32; 3] = [2.0, 0.0, -1.0];
+
+// Borrower notification preference flags, see `set_notification_prefs`.
+const NOTIFY_DUE_SOON: u32 = 1 << 0;
+const NOTIFY_OVERDUE: u32 = 1 << 1;
+const NOTIFY_REWARD: u32 = 1 << 2;
+const NOTIFY_GOAL_REACHED: u32 = 1 << 3;
+const NOTIFY_ALL: u32 = NOTIFY_DUE_SOON | NOTIFY_OVERDUE | NOTIFY_REWARD | NOTIFY_GOAL_REACHED;
+
+/// Feature bits reported by `capabilities`.
+const CAP_COLLATERAL: u32 = 1 << 0;
+const CAP_INSURANCE: u32 = 1 << 1;
+const CAP_BLEND: u32 = 1 << 2;
+const CAP_GOVERNANCE: u32 = 1 << 3;
+
 #[contract]
 pub struct MicroLoanContract {
     pool: LendingPool,
@@ -29,109 +487,4982 @@ pub struct MicroLoanContract {
 
 #[contractimpl]
 impl MicroLoanContract {
-    pub fn initialize(env: Env, owner: Address, initial_funds: i64, base_rate: f32) -> Self {
-        let pool = LendingPool {
+    /// Sets up the pool with `initial_funds` pulled from the owner, who gets
+    /// the matching shares. `base_rate` is the annual pool rate in percent.
+    pub fn initialize(env: Env, owner: Address, token: Address, fee_collector: Address, initial_funds: i64, base_rate: f32) -> Result<Self, LoanError> {
+        if initial_funds <= 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        if base_rate.is_nan() || base_rate < 0.0 {
+            return Err(LoanError::InvalidParameter);
+        }
+        token::Client::new(&env, &token).transfer(&owner, &env.current_contract_address(), &initial_funds);
+        let mut pool = LendingPool {
             total_funds: initial_funds,
-            loans: Vec::new(&env),
-            insurance_fund: initial_funds / 10,
+            loans: Map::new(&env),
+            insurance_fund: 0,
             base_interest_rate: base_rate,
+            insurance_funding_bps: 5_000,
+            next_loan_id: 1,
+            savings_bps: 500,
+            tapered_savings_bps: 200,
+            reward_threshold: 100_000_000,
+            token,
+            min_repayment: 1_000_000,
+            cooldown_secs: 0,
+            last_origination: Map::new(&env),
+            first_origination: Map::new(&env),
+            max_utilization_bps: 10_000,
+            notification_prefs: Map::new(&env),
+            collateral_token: None,
+            max_ltv_bps: 8_000,
+            interest_window_start: env.ledger().timestamp(),
+            interest_window_earned: 0,
+            last_window_earned: 0,
+            last_window_secs: 0,
+            lenders: Map::new(&env),
+            total_shares: initial_funds,
+            yield_per_share: 0,
+            listings: Map::new(&env),
+            next_listing_id: 1,
+            grace_period_secs: 5 * 24 * 60 * 60,
+            penalty_multiplier_bps: 15_000,
+            repayment_keys: Map::new(&env),
+            interest_insurance_bps: 0,
+            interest_yield_bps: 10_000,
+            credit_scores: Map::new(&env),
+            min_term_secs: 30 * 24 * 60 * 60,
+            max_term_secs: 2 * SECONDS_PER_YEAR,
+            min_loan_amount: 10_000_000,
+            max_loan_amount: 1_000_000_000,
+            origination_fee_bps: 0,
+            reserve_bps: 0,
+            underwriters: Vec::new(&env),
+            approval_quorum: 1,
+            large_loan_threshold: i64::MAX,
+            loan_approvals: Map::new(&env),
+            queued_changes: Map::new(&env),
+            next_change_id: 1,
+            max_capitalization_bps: 2_000,
+            min_loan_rate_bps: 50,
+            early_repayment_rebate_bps: 0,
+            early_repayment_penalty_bps: 0,
+            disbursement_delay_secs: 0,
+            loan_events: Map::new(&env),
+            rate_proposals: Map::new(&env),
+            next_proposal_id: 1,
+            token_symbols: Map::new(&env),
+            borrowed_totals: Map::new(&env),
+            savings_totals: Map::new(&env),
+            deposited_totals: Map::new(&env),
+            paused: false,
+            max_outflow_per_window: i64::MAX,
+            outflow_window_secs: 24 * 60 * 60,
+            outflow_window_start: env.ledger().timestamp(),
+            outflow_in_window: 0,
+            repaid_counts: Map::new(&env),
+            loyalty_discount_bps: 0,
+            max_loyalty_discount_bps: 0,
+            rounding_mode: RoundingMode::Down,
+            blacklist: Vec::new(&env),
+            blend_pool: None,
+            auto_return_savings: Map::new(&env),
+            max_loans: 10_000,
+            max_active_loans: u32::MAX,
+            defaulted_at: Map::new(&env),
+            default_cooldown_secs: 0,
+            compounding_period_secs: 0,
+            max_savings_per_loan: i64::MAX,
+            fee_collector,
+            insurance_from_savings: 0,
+            promo_until_ts: 0,
+            promo_grace_secs: 0,
+            late_fee_bps: 100,
+            max_late_fee_bps_of_principal: 500,
+            credit_bureau: None,
+            min_collateral_bps: 0,
+            disabled_bands: Vec::new(&env),
+            config_history: Vec::new(&env),
+            payment_application: PaymentApplication::InterestFirst,
+            version: 1,
+            variable_rate_loans: false,
+            keeper: None,
         };
-        Self { pool, owner }
+        pool.lenders.set(owner.clone(), LenderPosition { shares: initial_funds, reward_debt: 0, unclaimed: 0, listed: 0 });
+        log!(&env, "Pool initialized with {} from {}", initial_funds, owner);
+        Ok(Self { pool, owner })
     }
 
-    pub fn request_loan(&mut self, env: Env, borrower: Address, amount: i64) -> Result<(), &'static str> {
+    /// Opens a loan of `amount` over `term_secs`, pulling `collateral` of the
+    /// collateral token from the borrower up front. Collateral is valued at
+    /// face value and must cover `min_collateral_bps` of the amount. With
+    /// `disburse_to` set the proceeds go there, e.g. to a vendor, while the
+    /// borrower stays liable for the loan.
+    pub fn request_loan(&mut self, env: Env, borrower: Address, amount: i64, term_secs: u64, collateral: i64, disburse_to: Option<Address>) -> Result<u64, LoanError> {
         borrower.require_auth();
-        if amount < 10_000_000 || amount > 1_000_000_000 {
-            return Err("Loan amount must be between 1 XLM and 100 XLM");
+        self.open_loan(env, borrower, amount, LoanOffer::Regular { term_secs, collateral, disburse_to })
+    }
+
+    /// Opens a loan of `amount` on the terms of `offer`, once the borrower
+    /// has authorized it.
+    fn open_loan(&mut self, env: Env, borrower: Address, amount: i64, offer: LoanOffer) -> Result<u64, LoanError> {
+        let (term_secs, collateral, disburse_to, min_collateral_bps, rate) = match offer {
+            LoanOffer::Regular { term_secs, collateral, disburse_to } => (term_secs, collateral, disburse_to, self.pool.min_collateral_bps, None),
+            LoanOffer::Advance { rate } => (ADVANCE_TERM_SECS, 0, None, 0, Some(rate)),
+        };
+        if term_secs == 0 || term_secs < self.pool.min_term_secs || term_secs > self.pool.max_term_secs {
+            return Err(LoanError::InvalidTerm);
+        }
+        if collateral < 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        self.can_borrow(env.clone(), borrower.clone(), amount)?;
+        // Never let a bad `next_loan_id` overwrite a stored loan.
+        let id = self.pool.next_loan_id;
+        if self.pool.loans.contains_key(id) {
+            return Err(LoanError::IdCollision);
         }
-        if self.pool.total_funds < amount {
-            return Err("Insufficient funds in pool");
+        let required = (amount as i128 * min_collateral_bps as i128 + BPS_DENOMINATOR as i128 - 1) / BPS_DENOMINATOR as i128;
+        if (collateral as i128) < required {
+            return Err(LoanError::InsufficientCollateral);
         }
-        if !self.check_blend_pool_availability(amount) {
-            return Err("Blend pool unavailable");
+        if collateral > 0 {
+            let collateral_token = match self.pool.collateral_token.clone() {
+                Some(token) => token,
+                None => return Err(LoanError::CollateralDisabled),
+            };
+            token::Client::new(&env, &collateral_token).transfer(&borrower, &env.current_contract_address(), &collateral);
         }
-        let interest_rate = self.calculate_interest_rate();
-        let loan = Loan {
-            borrower,
+        let now = env.ledger().timestamp();
+        let interest_rate = rate.unwrap_or_else(|| self.offered_rate(&env, &borrower));
+        let denom = self.pool.token.clone();
+        let symbol = self.token_symbol(&env, &denom);
+        let mut loan = Loan {
+            id,
+            borrower: borrower.clone(),
             amount,
             interest_rate,
+            base_rate: interest_rate,
             repaid_amount: 0,
             savings: 0,
-            is_active: true,
+            status: LoanStatus::Pending,
+            accrued_interest: 0,
+            interest_paid: 0,
+            last_accrual_ts: now,
+            originated_at: now,
+            collateral,
+            term_secs,
+            capitalized_interest: 0,
+            min_rate_bps: self.pool.min_loan_rate_bps,
+            approved_at: 0,
+            denom,
+            symbol,
+            blend_funded: 0,
+            promo_ends_at: 0,
+            accrued_fees: 0,
+            accruing: true,
+            disburse_to,
+            variable_rate: self.pool.variable_rate_loans,
+            accrual_carry: 0,
+            period_interest: 0,
+            late_fee_installment: 0,
         };
-        self.pool.total_funds -= amount;
-        self.pool.loans.push_back(loan);
+        self.pool.last_origination.set(borrower.clone(), now);
+        if !self.pool.first_origination.contains_key(borrower.clone()) {
+            self.pool.first_origination.set(borrower.clone(), now);
+        }
+        self.pool.next_loan_id += 1;
+        if amount > self.pool.large_loan_threshold {
+            self.pool.loans.set(id, loan);
+            log!(&env, "Large loan {} pending approval: {} stroops by {}", id, amount, borrower);
+            return Ok(id);
+        }
+        self.approve_for_disbursement(&env, &mut loan);
+        self.pool.loans.set(id, loan);
         log!(&env, "Loan requested: {} stroops by {}", amount, borrower);
+        Ok(id)
+    }
+
+    /// Issues a short advance at the pool's base rate, without collateral,
+    /// to a borrower who has put away at least `ADVANCE_MIN_SAVINGS_BPS` of
+    /// `amount` per month on average since their first loan, archived or
+    /// not. Everything ever credited to their savings counts, withdrawn or
+    /// not. Apart from the rate, term and collateral it's a regular loan.
+    pub fn request_savings_advance(&mut self, env: Env, borrower: Address, amount: i64) -> Result<u64, LoanError> {
+        borrower.require_auth();
+        let now = env.ledger().timestamp();
+        let saved = self.pool.savings_totals.get(borrower.clone()).unwrap_or(0);
+        let first_loan_at = self.pool.first_origination.get(borrower.clone()).unwrap_or(now);
+        let months = ((now - first_loan_at) / INSTALLMENT_PERIOD_SECS).max(1) as i128;
+        if saved <= 0 || (saved as i128 * BPS_DENOMINATOR as i128) < amount as i128 * ADVANCE_MIN_SAVINGS_BPS as i128 * months {
+            return Err(LoanError::InsufficientSavingsHistory);
+        }
+        let rate = self.pool.base_interest_rate.max(self.pool.min_loan_rate_bps as f32 / 100.0);
+        let loan_id = self.open_loan(env.clone(), borrower.clone(), amount, LoanOffer::Advance { rate })?;
+        log!(&env, "Savings advance {} of {} for {}", loan_id, amount, borrower);
+        Ok(loan_id)
+    }
+
+    /// Records an underwriter's approval of a pending large loan and
+    /// approves it for disbursement once `approval_quorum` distinct
+    /// approvals are in.
+    pub fn approve_loan(&mut self, env: Env, underwriter: Address, loan_id: u64) -> Result<LoanStatus, LoanError> {
+        underwriter.require_auth();
+        if !self.pool.underwriters.contains(&underwriter) {
+            return Err(LoanError::NotUnderwriter);
+        }
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) => loan,
+            None => return Err(LoanError::LoanNotFound),
+        };
+        if loan.status != LoanStatus::Pending {
+            return Err(LoanError::LoanNotPending);
+        }
+        let mut approvals = self.pool.loan_approvals.get(loan_id).unwrap_or(Vec::new(&env));
+        if approvals.contains(&underwriter) {
+            return Err(LoanError::AlreadyApproved);
+        }
+        approvals.push_back(underwriter.clone());
+        log!(&env, "Loan {} approved by {} ({} of {})", loan_id, underwriter, approvals.len(), self.pool.approval_quorum);
+        if approvals.len() < self.pool.approval_quorum {
+            self.pool.loan_approvals.set(loan_id, approvals);
+            return Ok(loan.status);
+        }
+        if self.pool.paused {
+            return Err(LoanError::LendingPaused);
+        }
+        self.check_funding(&env, loan.amount)?;
+        self.approve_for_disbursement(&env, &mut loan);
+        let status = loan.status;
+        self.pool.loans.set(loan_id, loan);
+        self.pool.loan_approvals.remove(loan_id);
+        Ok(status)
+    }
+
+    /// Pays out an approved loan once `disbursement_delay_secs` have passed
+    /// since its approval.
+    pub fn disburse(&mut self, env: Env, borrower: Address, loan_id: u64) -> Result<(), LoanError> {
+        borrower.require_auth();
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.borrower == borrower && loan.status == LoanStatus::ReadyToDisburse => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        if self.pool.paused {
+            return Err(LoanError::LendingPaused);
+        }
+        if env.ledger().timestamp() < loan.approved_at + self.pool.disbursement_delay_secs {
+            return Err(LoanError::DisbursementDelayActive);
+        }
+        self.check_funding(&env, loan.amount)?;
+        self.activate_loan(&env, &mut loan);
+        self.pool.loans.set(loan_id, loan);
+        Ok(())
+    }
+
+    /// Calls off a loan that was never disbursed, whether still `Pending`
+    /// approval or `ReadyToDisburse`, and hands its collateral back. Either
+    /// the borrower or the owner may cancel. The loan is dropped from
+    /// storage; its record is published in a `cancelled` event and returned.
+    pub fn cancel_loan(&mut self, env: Env, caller: Address, loan_id: u64) -> Result<Loan, LoanError> {
+        caller.require_auth();
+        let loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.status == LoanStatus::Pending || loan.status == LoanStatus::ReadyToDisburse => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        if caller != loan.borrower && caller != self.owner {
+            return Err(LoanError::Unauthorized);
+        }
+        if loan.collateral > 0 {
+            let collateral_token = self.pool.collateral_token.clone().unwrap();
+            token::Client::new(&env, &collateral_token).transfer(&env.current_contract_address(), &loan.borrower, &loan.collateral);
+        }
+        self.pool.loans.remove(loan_id);
+        self.pool.loan_approvals.remove(loan_id);
+        env.events().publish((symbol_short!("cancelled"), loan_id), loan.clone());
+        log!(&env, "Loan {} cancelled by {}", loan_id, caller);
+        Ok(loan)
+    }
+
+    /// Sets how many loans the pool may hold in storage; originations are
+    /// rejected once it's reached.
+    pub fn set_max_loans(&mut self, env: Env, owner: Address, max_loans: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if max_loans == 0 {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "max_loans", self.pool.max_loans, max_loans);
+        self.pool.max_loans = max_loans;
+        log!(&env, "Max loans set to {}", max_loans);
+        Ok(())
+    }
+
+    /// Caps how many loans may be open at once, pending or active, whoever
+    /// holds them. Unlike `max_loans`, repaid and defaulted loans don't count.
+    pub fn set_max_active_loans(&mut self, env: Env, owner: Address, max_active_loans: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if max_active_loans == 0 {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "max_active_loans", self.pool.max_active_loans, max_active_loans);
+        self.pool.max_active_loans = max_active_loans;
+        log!(&env, "Max active loans set to {}", max_active_loans);
+        Ok(())
+    }
+
+    /// Sets the cooling-off period between a loan's approval and its
+    /// disbursement. Zero disburses loans as soon as they're approved.
+    pub fn set_disbursement_delay_secs(&mut self, env: Env, owner: Address, delay_secs: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_num(&env, &owner, "disbursement_delay_secs", self.pool.disbursement_delay_secs, delay_secs);
+        self.pool.disbursement_delay_secs = delay_secs;
+        log!(&env, "Disbursement delay set to {} seconds", delay_secs);
+        Ok(())
+    }
+
+    pub fn set_rounding_mode(&mut self, env: Env, owner: Address, mode: RoundingMode) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_num(&env, &owner, "rounding_mode", self.pool.rounding_mode as u32, mode as u32);
+        self.pool.rounding_mode = mode;
+        log!(&env, "Rounding mode set to {}", mode as u32);
+        Ok(())
+    }
+
+    pub fn set_payment_application(&mut self, env: Env, owner: Address, order: PaymentApplication) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_num(&env, &owner, "payment_application", self.pool.payment_application as u32, order as u32);
+        self.pool.payment_application = order;
+        log!(&env, "Payment application set to {}", order as u32);
+        Ok(())
+    }
+
+    /// Configures the repeat-borrower discount on the origination fee:
+    /// `per_loan_bps` off for each fully repaid loan, at most `max_bps`.
+    pub fn set_loyalty_discount(&mut self, env: Env, owner: Address, per_loan_bps: u32, max_bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if per_loan_bps > max_bps || max_bps as i64 > BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "loyalty_discount_bps", self.pool.loyalty_discount_bps, per_loan_bps);
+        self.pool.loyalty_discount_bps = per_loan_bps;
+        self.record_config_num(&env, &owner, "max_loyalty_discount_bps", self.pool.max_loyalty_discount_bps, max_bps);
+        self.pool.max_loyalty_discount_bps = max_bps;
+        log!(&env, "Loyalty discount set: {} bps per repaid loan, up to {} bps", per_loan_bps, max_bps);
+        Ok(())
+    }
+
+    /// Sets the credit bureau consulted at origination and told about
+    /// repayments and defaults. With none set, only internal scores are used.
+    pub fn set_credit_bureau(&mut self, env: Env, owner: Address, credit_bureau: Option<Address>) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_change(&env, &owner, "credit_bureau", ConfigValue::from(self.pool.credit_bureau.clone()), ConfigValue::from(credit_bureau.clone()));
+        self.pool.credit_bureau = credit_bureau;
+        log!(&env, "Credit bureau updated");
+        Ok(())
+    }
+
+    /// Sets the keeper allowed to run `batch_accrue` alongside the owner.
+    pub fn set_keeper(&mut self, env: Env, owner: Address, keeper: Option<Address>) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_change(&env, &owner, "keeper", ConfigValue::from(self.pool.keeper.clone()), ConfigValue::from(keeper.clone()));
+        self.pool.keeper = keeper;
+        log!(&env, "Keeper updated");
+        Ok(())
+    }
+
+    /// Sets the Blend pool loans may draw on when the pool's own funds fall
+    /// short. It can't be changed while Blend is still owed principal.
+    pub fn set_blend_pool(&mut self, env: Env, owner: Address, blend_pool: Option<Address>) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if self.loans().iter().any(|l| l.blend_funded > 0) {
+            return Err(LoanError::BlendBalanceOutstanding);
+        }
+        self.record_config_change(&env, &owner, "blend_pool", ConfigValue::from(self.pool.blend_pool.clone()), ConfigValue::from(blend_pool.clone()));
+        self.pool.blend_pool = blend_pool;
+        log!(&env, "Blend pool updated");
+        Ok(())
+    }
+
+    /// Configures the circuit breaker: new lending is paused once more than
+    /// `max_outflow_per_window` leaves the pool within one window.
+    pub fn set_circuit_breaker(&mut self, env: Env, owner: Address, max_outflow_per_window: i64, outflow_window_secs: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if max_outflow_per_window <= 0 || outflow_window_secs == 0 {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "max_outflow_per_window", self.pool.max_outflow_per_window, max_outflow_per_window);
+        self.pool.max_outflow_per_window = max_outflow_per_window;
+        self.record_config_num(&env, &owner, "outflow_window_secs", self.pool.outflow_window_secs, outflow_window_secs);
+        self.pool.outflow_window_secs = outflow_window_secs;
+        log!(&env, "Circuit breaker set: {} per {} seconds", max_outflow_per_window, outflow_window_secs);
+        Ok(())
+    }
+
+    /// Lifts a circuit-breaker pause and starts a fresh outflow window.
+    pub fn resume_lending(&mut self, env: Env, owner: Address) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.pool.paused = false;
+        self.pool.outflow_window_start = env.ledger().timestamp();
+        self.pool.outflow_in_window = 0;
+        log!(&env, "Lending resumed");
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pool.paused
+    }
+
+    /// Configures who may approve large loans, how many approvals a loan above
+    /// `large_loan_threshold` needs, and the threshold itself.
+    pub fn set_underwriters(&mut self, env: Env, owner: Address, underwriters: Vec<Address>, quorum: u32, large_loan_threshold: i64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if quorum == 0 || quorum > underwriters.len() || large_loan_threshold <= 0 {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "underwriters", self.pool.underwriters.len(), underwriters.len());
+        self.pool.underwriters = underwriters;
+        self.record_config_num(&env, &owner, "approval_quorum", self.pool.approval_quorum, quorum);
+        self.pool.approval_quorum = quorum;
+        self.record_config_num(&env, &owner, "large_loan_threshold", self.pool.large_loan_threshold, large_loan_threshold);
+        self.pool.large_loan_threshold = large_loan_threshold;
+        log!(&env, "Underwriters set: quorum {} above {}", quorum, large_loan_threshold);
+        Ok(())
+    }
+
+    /// Read-only eligibility check for a loan of `amount`, returning the
+    /// first reason `request_loan` would reject it. `request_loan` runs
+    /// exactly these checks, so the two can't drift apart.
+    pub fn can_borrow(&self, env: Env, borrower: Address, amount: i64) -> Result<(), LoanError> {
+        if self.pool.paused {
+            return Err(LoanError::LendingPaused);
+        }
+        self.check_borrower(&env, &borrower)?;
+        if self.pool.loans.len() >= self.pool.max_loans {
+            return Err(LoanError::LoanCapReached);
+        }
+        let open = self.loans().iter().filter(|l| l.status != LoanStatus::Repaid && l.status != LoanStatus::Defaulted).count() as u32;
+        if open >= self.pool.max_active_loans {
+            return Err(LoanError::ActiveLoanCapReached);
+        }
+        if amount < self.pool.min_loan_amount || amount > self.pool.max_loan_amount {
+            return Err(LoanError::AmountOutOfRange);
+        }
+        self.check_funding(&env, amount)?;
+        if self.utilization_bps_after(amount - self.blend_draw_for(amount)) > self.pool.max_utilization_bps as i64 {
+            return Err(LoanError::UtilizationCapExceeded);
+        }
+        Ok(())
+    }
+
+    /// The checks on the borrower themselves, shared by `can_borrow` and
+    /// `transfer_loan`: blacklist, paused credit band, and the origination
+    /// and default cooldowns.
+    fn check_borrower(&self, env: &Env, borrower: &Address) -> Result<(), LoanError> {
+        if self.pool.blacklist.contains(borrower) {
+            return Err(LoanError::BorrowerBlacklisted);
+        }
+        if !self.pool.disabled_bands.is_empty() {
+            let score = self.blended_credit_score(env, borrower);
+            let band_floor = CREDIT_BANDS[CREDIT_BANDS.iter().rposition(|floor| score >= *floor).unwrap_or(0)];
+            if self.pool.disabled_bands.contains(&band_floor) {
+                return Err(LoanError::BandLendingPaused);
+            }
+        }
+        if let Some(last) = self.pool.last_origination.get(borrower.clone()) {
+            if env.ledger().timestamp() < last + self.pool.cooldown_secs {
+                return Err(LoanError::BorrowerInCooldown);
+            }
+        }
+        if let Some(defaulted) = self.pool.defaulted_at.get(borrower.clone()) {
+            if env.ledger().timestamp() < defaulted + self.pool.default_cooldown_secs {
+                return Err(LoanError::DefaultCooldownActive);
+            }
+        }
         Ok(())
     }
 
-    pub fn repay_loan(&mut self, env: Env, borrower: Address, amount: i64) -> Result<(), &'static str> {
+    /// Repays the borrower's active or defaulted loan. A repayment carrying an
+    /// `idempotency_key` already seen from this borrower within
+    /// `IDEMPOTENCY_RETENTION_SECS` is treated as a retry and does nothing.
+    pub fn repay_loan(&mut self, env: Env, borrower: Address, amount: i64, idempotency_key: Option<BytesN<32>>) -> Result<(), LoanError> {
         borrower.require_auth();
-        let loan = self.pool.loans.iter_mut().find(|l| l.borrower == borrower && l.is_active);
-        match loan {
-            Some(loan) => {
-                if amount <= 0 {
-                    return Err("Invalid repayment amount");
+        let now = env.ledger().timestamp();
+        if let Some(key) = &idempotency_key {
+            if self.is_repeat_repayment(&borrower, key, now) {
+                log!(&env, "Duplicate repayment ignored for {}", borrower);
+                return Ok(());
+            }
+        }
+        let loan_id = self.loans().iter()
+            .find(|l| l.borrower == borrower && (l.status == LoanStatus::Active || l.status == LoanStatus::Defaulted))
+            .map(|l| l.id);
+        match loan_id {
+            Some(loan_id) => {
+                self.apply_repayment(&env, &borrower, loan_id, amount)?;
+                if let Some(key) = idempotency_key {
+                    self.record_repayment_key(&env, &borrower, key, now);
                 }
-                loan.repaid_amount += amount;
-                let savings = amount / 20;
-                loan.savings += savings;
-                if loan.savings >= 100_000_000 && loan.interest_rate > 0.5 {
-                    loan.interest_rate -= 0.5;
-                    log!(&env, "Reward: Interest rate reduced to {}", loan.interest_rate);
+                Ok(())
+            }
+            None => Err(LoanError::LoanNotFound),
+        }
+    }
+
+    /// Lets a third party pay down someone else's loan. The payer's tokens
+    /// are applied exactly as the borrower's own repayment would be,
+    /// including the savings credited to the borrower.
+    pub fn repay_on_behalf(&mut self, env: Env, payer: Address, borrower: Address, loan_id: u64, amount: i64) -> Result<(), LoanError> {
+        payer.require_auth();
+        match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.borrower == borrower && (loan.status == LoanStatus::Active || loan.status == LoanStatus::Defaulted) => {
+                self.apply_repayment(&env, &payer, loan_id, amount)?;
+                log!(&env, "Loan {} repaid by {} on behalf of {}", loan_id, payer, borrower);
+                Ok(())
+            }
+            _ => Err(LoanError::LoanNotFound),
+        }
+    }
+
+    /// Quotes how a repayment of `amount` on `loan_id` would be split into
+    /// `(to_principal, to_interest, to_savings, to_insurance, penalty)` if
+    /// made now, using the same accrual, rebate and split as `repay_loan`.
+    /// `penalty` is the prepayment penalty charged on top of `amount`.
+    /// Nothing is mutated.
+    pub fn preview_repayment(&self, env: Env, loan_id: u64, amount: i64) -> Result<(i64, i64, i64, i64, i64), LoanError> {
+        if amount <= 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.status == LoanStatus::Active => {
+                let (_, split, penalty) = self.plan_repayment(&loan, amount, env.ledger().timestamp());
+                Ok((split.to_principal, split.to_interest, split.to_savings, split.to_insurance + split.interest_to_insurance, penalty))
+            }
+            _ => Err(LoanError::LoanNotFound),
+        }
+    }
+
+    /// Records a borrower's credit score, as assessed off-chain by the owner.
+    pub fn set_credit_score(&mut self, env: Env, owner: Address, borrower: Address, score: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if score > MAX_CREDIT_SCORE {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.pool.credit_scores.set(borrower.clone(), score);
+        log!(&env, "Credit score for {} set to {}", borrower, score);
+        Ok(())
+    }
+
+    /// Stops or restarts new lending to the credit band starting at
+    /// `band_floor`, one of `CREDIT_BANDS`. Other bands are unaffected.
+    pub fn set_band_lending(&mut self, env: Env, owner: Address, band_floor: u32, enabled: bool) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        let param = match CREDIT_BANDS.iter().position(|b| *b == band_floor) {
+            Some(band) => CREDIT_BAND_PARAMS[band],
+            None => return Err(LoanError::InvalidParameter),
+        };
+        let index = self.pool.disabled_bands.first_index_of(&band_floor);
+        self.record_config_change(&env, &owner, param, ConfigValue::Flag(index.is_none()), ConfigValue::Flag(enabled));
+        match (enabled, index) {
+            (true, Some(i)) => {
+                self.pool.disabled_bands.remove(i);
+            }
+            (false, None) => self.pool.disabled_bands.push_back(band_floor),
+            _ => {}
+        }
+        log!(&env, "Lending to band {} set to {}", band_floor, enabled);
+        Ok(())
+    }
+
+    pub fn get_credit_score(&self, borrower: Address) -> u32 {
+        self.pool.credit_scores.get(borrower).unwrap_or(DEFAULT_CREDIT_SCORE)
+    }
+
+    /// Active loans grouped by the borrower's credit band, as
+    /// `(band_floor, loan_count, outstanding)` for every band in `CREDIT_BANDS`.
+    pub fn portfolio_by_band(&self, env: Env) -> Vec<(u32, u32, i64)> {
+        let now = env.ledger().timestamp();
+        let mut bands = Vec::new(&env);
+        for (i, floor) in CREDIT_BANDS.iter().enumerate() {
+            let ceiling = CREDIT_BANDS.get(i + 1).copied().unwrap_or(u32::MAX);
+            let mut count = 0u32;
+            let mut outstanding = 0i64;
+            for loan in self.loans().iter().filter(|l| l.status == LoanStatus::Active) {
+                let score = self.get_credit_score(loan.borrower.clone());
+                if score >= *floor && score < ceiling {
+                    count += 1;
+                    outstanding += self.remaining_balance(&loan, now);
                 }
-                self.pool.total_funds += amount - savings;
-                self.pool.insurance_fund += savings / 2;
-                if loan.repaid_amount >= loan.amount {
-                    loan.is_active = false;
-                    log!(&env, "Loan fully repaid by {}", borrower);
+            }
+            bands.push_back((*floor, count, outstanding));
+        }
+        bands
+    }
+
+    /// Seconds since the loan was originated, as of the current ledger time.
+    pub fn loan_age_secs(&self, env: Env, loan_id: u64) -> Result<u64, LoanError> {
+        match self.pool.loans.get(loan_id) {
+            Some(loan) => Ok(env.ledger().timestamp().saturating_sub(loan.originated_at)),
+            None => Err(LoanError::LoanNotFound),
+        }
+    }
+
+    /// Active loans counted by how far past due their earliest unpaid
+    /// installment is, as `(bucket_floor_days, loan_count)` for every bucket
+    /// in `AGING_BUCKETS`. Part of a day past due counts as a whole day.
+    pub fn aging_report(&self, env: Env) -> Vec<(u32, u32)> {
+        let now = env.ledger().timestamp();
+        let mut counts = [0u32; 4];
+        for loan in self.loans().iter().filter(|l| l.status == LoanStatus::Active) {
+            let days = match Self::next_due_ts(&loan) {
+                Some(due) if now > due => (now - due + SECONDS_PER_DAY - 1) / SECONDS_PER_DAY,
+                _ => 0,
+            };
+            let bucket = AGING_BUCKETS.iter().rposition(|floor| days >= *floor as u64).unwrap_or(0);
+            counts[bucket] += 1;
+        }
+        let mut report = Vec::new(&env);
+        for (floor, count) in AGING_BUCKETS.iter().zip(counts.iter()) {
+            report.push_back((*floor, *count));
+        }
+        report
+    }
+
+    /// The `n` borrowers with the most principal disbursed to them over all
+    /// their loans, largest first. `n` is capped at `MAX_LEADERBOARD_SIZE`.
+    pub fn top_borrowers(&self, env: Env, n: u32) -> Vec<(Address, i64)> {
+        Self::top_n(&env, &self.pool.borrowed_totals, n)
+    }
+
+    /// The `n` lenders with the largest cumulative deposits, largest first.
+    pub fn top_depositors(&self, env: Env, n: u32) -> Vec<(Address, i64)> {
+        Self::top_n(&env, &self.pool.deposited_totals, n)
+    }
+
+    fn top_n(env: &Env, totals: &Map<Address, i64>, n: u32) -> Vec<(Address, i64)> {
+        let n = n.min(MAX_LEADERBOARD_SIZE);
+        let mut top: Vec<(Address, i64)> = Vec::new(env);
+        if n == 0 {
+            return top;
+        }
+        for (address, total) in totals.iter() {
+            let at = top.iter().position(|(_, t)| t < total).map(|i| i as u32).unwrap_or(top.len());
+            if at < n {
+                top.insert(at, (address, total));
+                if top.len() > n {
+                    top.pop_back();
                 }
-                Ok(())
             }
-            None => Err("No active loan found"),
         }
+        top
     }
 
-    fn calculate_interest_rate(&self) -> f32 {
-        let utilization = self.pool.loans.iter().filter(|l| l.is_active).map(|l| l.amount).sum::<i64>() as f32 / self.pool.total_funds as f32;
-        self.pool.base_interest_rate + (utilization * 2.0).min(5.0)
+    /// Stores which notifications an off-chain watcher should send the
+    /// borrower, as a bitmask of the `NOTIFY_*` flags.
+    pub fn set_notification_prefs(&mut self, env: Env, borrower: Address, flags: u32) -> Result<(), LoanError> {
+        borrower.require_auth();
+        if flags & !NOTIFY_ALL != 0 {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.pool.notification_prefs.set(borrower.clone(), flags);
+        log!(&env, "Notification preferences set to {} for {}", flags, borrower);
+        Ok(())
     }
 
-    fn check_blend_pool_availability(&self, amount: i64) -> bool {
-        self.pool.total_funds >= amount
+    pub fn get_notification_prefs(&self, borrower: Address) -> u32 {
+        self.pool.notification_prefs.get(borrower).unwrap_or(0)
     }
 
-    pub fn get_loan_status(&self, borrower: Address) -> Option<Loan> {
-        self.pool.loans.iter().find(|l| l.borrower == borrower && l.is_active).cloned()
+    /// Whether a loan's savings are paid back automatically when it's fully
+    /// repaid. Otherwise they wait for `withdraw_savings`.
+    pub fn set_auto_return_savings(&mut self, env: Env, borrower: Address, enabled: bool) -> Result<(), LoanError> {
+        borrower.require_auth();
+        self.pool.auto_return_savings.set(borrower.clone(), enabled);
+        log!(&env, "Auto-return of savings set to {} for {}", enabled, borrower);
+        Ok(())
     }
 
-    pub fn get_pool_stats(&self) -> (i64, u32, i64) {
-        let active_loans = self.pool.loans.iter().filter(|l| l.is_active).count() as u32;
-        let total_savings = self.pool.loans.iter().map(|l| l.savings).sum::<i64>();
-        (self.pool.total_funds, active_loans, total_savings)
+    /// Pays out the savings of a fully repaid loan.
+    pub fn withdraw_savings(&mut self, env: Env, borrower: Address, loan_id: u64) -> Result<i64, LoanError> {
+        borrower.require_auth();
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.borrower == borrower => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        if loan.status != LoanStatus::Repaid {
+            return Err(LoanError::LoanNotRepaid);
+        }
+        let amount = loan.savings;
+        loan.savings = 0;
+        self.pool.loans.set(loan_id, loan);
+        if amount > 0 {
+            token::Client::new(&env, &self.pool.token).transfer(&env.current_contract_address(), &borrower, &amount);
+            log!(&env, "Savings withdrawn: {} by {}", amount, borrower);
+        }
+        Ok(amount)
     }
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
-        use soroban_sdk::testutils::{Address as _, Ledger};
+    /// Adds `amount` from `from` straight to the insurance fund, for donors
+    /// and partner protocols that want to back the pool's loss cover.
+    pub fn contribute_to_insurance(&mut self, env: Env, from: Address, amount: i64) -> Result<(), LoanError> {
+        from.require_auth();
+        if amount <= 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        token::Client::new(&env, &self.pool.token).transfer(&from, &env.current_contract_address(), &amount);
+        self.pool.insurance_fund += amount;
+        env.events().publish((symbol_short!("donation"), from.clone()), amount);
+        log!(&env, "Insurance contribution: {} from {}", amount, from);
+        Ok(())
+    }
 
-        #[test]
-        fn test_loan_lifecycle() {
-            let env = Env::default();
-            let owner = Address::random(&env);
-            let contract = MicroLoanContract::initialize(&env, owner.clone(), 10_000_000_000, 5.0);
-            let borrower = Address::random(&env);
-            let mut contract = contract;
+    /// Sets the share of each repayment's savings routed to the insurance fund.
+    pub fn set_insurance_funding_bps(&mut self, env: Env, owner: Address, bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if bps as i64 > BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "insurance_funding_bps", self.pool.insurance_funding_bps, bps);
+        self.pool.insurance_funding_bps = bps;
+        log!(&env, "Insurance funding ratio set to {} bps", bps);
+        Ok(())
+    }
+
+    /// Sets how repaid interest is allocated: `insurance_bps` to the insurance
+    /// fund, `yield_bps` to lenders as claimable yield, and the remainder kept
+    /// in `total_funds`.
+    pub fn set_reserve_split(&mut self, env: Env, owner: Address, insurance_bps: u32, yield_bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if (insurance_bps + yield_bps) as i64 > BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "interest_insurance_bps", self.pool.interest_insurance_bps, insurance_bps);
+        self.pool.interest_insurance_bps = insurance_bps;
+        self.record_config_num(&env, &owner, "interest_yield_bps", self.pool.interest_yield_bps, yield_bps);
+        self.pool.interest_yield_bps = yield_bps;
+        log!(&env, "Reserve split set: {} bps insurance, {} bps yield", insurance_bps, yield_bps);
+        Ok(())
+    }
+
+    /// Configures the forced-savings taper: `full_bps` of each repayment is
+    /// saved until the loan's savings reach `threshold`, `tapered_bps` after.
+    /// The same threshold triggers the interest rate reward.
+    pub fn set_savings_taper(&mut self, env: Env, owner: Address, full_bps: u32, tapered_bps: u32, threshold: i64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if full_bps as i64 > BPS_DENOMINATOR || tapered_bps > full_bps {
+            return Err(LoanError::InvalidParameter);
+        }
+        if threshold <= 0 {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "savings_bps", self.pool.savings_bps, full_bps);
+        self.pool.savings_bps = full_bps;
+        self.record_config_num(&env, &owner, "tapered_savings_bps", self.pool.tapered_savings_bps, tapered_bps);
+        self.pool.tapered_savings_bps = tapered_bps;
+        self.record_config_num(&env, &owner, "reward_threshold", self.pool.reward_threshold, threshold);
+        self.pool.reward_threshold = threshold;
+        log!(&env, "Savings taper set: {} bps, {} bps past {}", full_bps, tapered_bps, threshold);
+        Ok(())
+    }
+
+    /// Caps the savings held on any one loan. Once a loan's savings reach
+    /// the cap, its repayments go to the pool in full.
+    pub fn set_max_savings_per_loan(&mut self, env: Env, owner: Address, max_savings: i64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if max_savings < 0 {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "max_savings_per_loan", self.pool.max_savings_per_loan, max_savings);
+        self.pool.max_savings_per_loan = max_savings;
+        log!(&env, "Max savings per loan set to {}", max_savings);
+        Ok(())
+    }
 
-            assert!(contract.request_loan(&env, borrower.clone(), 500_000_000).is_ok());
-            let loan = contract.get_loan_status(borrower.clone()).unwrap();
-            assert_eq!(loan.amount, 500_000_000);
-            assert_eq!(loan.is_active, true);
+    /// Switches the pool's lending asset. Only allowed once no loan is open
+    /// or awaiting disbursement and the contract holds none of the old token,
+    /// so no obligation is left denominated in it.
+    pub fn set_token(&mut self, env: Env, owner: Address, new_token: Address) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if Some(new_token.clone()) == self.pool.collateral_token {
+            return Err(LoanError::InvalidParameter);
+        }
+        if self.loans().iter().any(|l| l.status != LoanStatus::Repaid) {
+            return Err(LoanError::LoansOutstanding);
+        }
+        if token::Client::new(&env, &self.pool.token).balance(&env.current_contract_address()) != 0 {
+            return Err(LoanError::TokenBalanceRemaining);
+        }
+        log!(&env, "Lending token changed from {} to {}", self.pool.token, new_token);
+        self.record_config_change(&env, &owner, "token", ConfigValue::Addr(self.pool.token.clone()), ConfigValue::Addr(new_token.clone()));
+        self.pool.token = new_token;
+        Ok(())
+    }
 
-            assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000).is_ok());
-            let loan = contract.get_loan_status(borrower.clone()).unwrap();
-            assert_eq!(loan.repaid_amount, 100_000_000);
-            assert_eq!(loan.savings, 5_000_000);
+    /// Returns tokens sent to the contract by mistake. The pool's lending
+    /// token can never be recovered this way.
+    pub fn recover_token(&self, env: Env, owner: Address, token: Address, to: Address, amount: i64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if token == self.pool.token || Some(token.clone()) == self.pool.collateral_token {
+            return Err(LoanError::ProtectedToken);
+        }
+        if amount <= 0 {
+            return Err(LoanError::InvalidAmount);
         }
-    }
\ No newline at end of file
+        token::Client::new(&env, &token).transfer(&env.current_contract_address(), &to, &amount);
+        log!(&env, "Recovered {} of token {} to {}", amount, token, to);
+        Ok(())
+    }
+
+    /// The lending-token balance the books account for (idle funds,
+    /// insurance, borrower savings and lender yield not yet claimed) next to
+    /// the contract's actual balance, as `(accounted, actual)`.
+    pub fn reconcile(&self, env: Env, owner: Address) -> Result<(i64, i64), LoanError> {
+        self.require_owner(&owner)?;
+        let savings = self.loans().iter().map(|l| l.savings).sum::<i64>();
+        let mut unclaimed = 0i64;
+        for (lender, _) in self.pool.lenders.iter() {
+            unclaimed += self.settle_lender(&lender).unclaimed;
+        }
+        let accounted = self.pool.total_funds + self.pool.insurance_fund + savings + unclaimed;
+        let actual = token::Client::new(&env, &self.pool.token).balance(&env.current_contract_address()) as i64;
+        Ok((accounted, actual))
+    }
+
+    /// Credits any balance the books don't account for, such as a direct
+    /// transfer into the contract, to the insurance fund. Returns the amount
+    /// absorbed.
+    pub fn absorb_surplus(&mut self, env: Env, owner: Address) -> Result<i64, LoanError> {
+        let (accounted, actual) = self.reconcile(env.clone(), owner)?;
+        let surplus = (actual - accounted).max(0);
+        if surplus > 0 {
+            self.pool.insurance_fund += surplus;
+            log!(&env, "Absorbed {} surplus into insurance", surplus);
+        }
+        Ok(surplus)
+    }
+
+    /// The token's symbol, fetched from the token contract on first use.
+    fn token_symbol(&mut self, env: &Env, token: &Address) -> String {
+        if let Some(symbol) = self.pool.token_symbols.get(token.clone()) {
+            return symbol;
+        }
+        let symbol = token::Client::new(env, token).symbol();
+        self.pool.token_symbols.set(token.clone(), symbol.clone());
+        symbol
+    }
+
+    fn require_owner(&self, owner: &Address) -> Result<(), LoanError> {
+        owner.require_auth();
+        if *owner != self.owner {
+            return Err(LoanError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    pub fn loan_statement(&self, env: Env, loan_id: u64) -> Result<Statement, LoanError> {
+        match self.pool.loans.get(loan_id) {
+            Some(loan) => {
+                let interest_accrued = if loan.status == LoanStatus::Active {
+                    loan.accrued_interest + self.pending_interest(&loan, env.ledger().timestamp())
+                } else {
+                    loan.accrued_interest
+                };
+                // Capitalized interest lives in `amount`; report it as interest.
+                Ok(Statement {
+                    originated_at: loan.originated_at,
+                    amount: loan.amount - loan.capitalized_interest,
+                    interest_rate: loan.interest_rate,
+                    interest_accrued: interest_accrued + loan.capitalized_interest,
+                    total_repaid: loan.repaid_amount,
+                    savings: loan.savings,
+                    remaining_balance: (loan.amount + interest_accrued - loan.repaid_amount).max(0),
+                    status: loan.status,
+                    denom: loan.denom.clone(),
+                    symbol: loan.symbol.clone(),
+                })
+            }
+            None => Err(LoanError::LoanNotFound),
+        }
+    }
+
+    /// Projects what it would take to pay the loan off at `future_ts`,
+    /// accruing interest (at the penalty rate where overdue) up to then. Any
+    /// early repayment rebate is taken off; a prepayment penalty is charged
+    /// on top of this amount.
+    pub fn estimate_payoff_at(&self, env: Env, loan_id: u64, future_ts: u64) -> Result<i64, LoanError> {
+        if future_ts < env.ledger().timestamp() {
+            return Err(LoanError::InvalidParameter);
+        }
+        match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.status == LoanStatus::Active => {
+                Ok(self.remaining_balance(&loan, future_ts) - self.early_repayment_rebate(&loan, future_ts))
+            }
+            _ => Err(LoanError::LoanNotFound),
+        }
+    }
+
+    /// Sets the smallest accepted repayment. A payment that settles the whole
+    /// remaining balance is always accepted, even if it is smaller.
+    pub fn set_min_repayment(&mut self, env: Env, owner: Address, min_repayment: i64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if min_repayment < 0 {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "min_repayment", self.pool.min_repayment, min_repayment);
+        self.pool.min_repayment = min_repayment;
+        log!(&env, "Minimum repayment set to {}", min_repayment);
+        Ok(())
+    }
+
+    /// Deposits `amount` of the lending token into the pool, minting the same
+    /// number of shares to `lender`.
+    pub fn deposit(&mut self, env: Env, lender: Address, amount: i64) -> Result<i64, LoanError> {
+        lender.require_auth();
+        if amount <= 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        token::Client::new(&env, &self.pool.token).transfer(&lender, &env.current_contract_address(), &amount);
+        let mut position = self.settle_lender(&lender);
+        position.shares += amount;
+        self.store_lender(&lender, position);
+        self.pool.total_shares += amount;
+        self.pool.total_funds += amount;
+        let deposited = self.pool.deposited_totals.get(lender.clone()).unwrap_or(0);
+        self.pool.deposited_totals.set(lender.clone(), deposited + amount);
+        log!(&env, "Deposit: {} by {}", amount, lender);
+        Ok(amount)
+    }
+
+    /// Burns `shares` and returns the same amount of the lending token, as
+    /// long as the pool has that much idle above the reserve.
+    pub fn withdraw(&mut self, env: Env, lender: Address, shares: i64) -> Result<i64, LoanError> {
+        lender.require_auth();
+        if shares <= 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        let mut position = self.settle_lender(&lender);
+        if position.shares - position.listed < shares {
+            return Err(LoanError::InsufficientShares);
+        }
+        if self.pool.total_funds - self.required_reserve() < shares {
+            return Err(LoanError::InsufficientFunds);
+        }
+        position.shares -= shares;
+        self.store_lender(&lender, position);
+        self.pool.total_shares -= shares;
+        self.pool.total_funds -= shares;
+        self.record_outflow(&env, shares);
+        token::Client::new(&env, &self.pool.token).transfer(&env.current_contract_address(), &lender, &shares);
+        log!(&env, "Withdrawal: {} by {}", shares, lender);
+        Ok(shares)
+    }
+
+    /// Withdraws as much of the lender's unlisted shares as the pool's idle
+    /// funds above the reserve allow. Shares that can't be paid out yet stay
+    /// with the lender; the `wd_all` event reports `(withdrawn, locked)`.
+    pub fn withdraw_all(&mut self, env: Env, lender: Address) -> Result<i64, LoanError> {
+        lender.require_auth();
+        let mut position = self.settle_lender(&lender);
+        let available = position.shares - position.listed;
+        if available <= 0 {
+            return Err(LoanError::InsufficientShares);
+        }
+        let amount = available.min((self.pool.total_funds - self.required_reserve()).max(0));
+        let locked = available - amount;
+        if amount > 0 {
+            position.shares -= amount;
+            self.pool.total_shares -= amount;
+            self.pool.total_funds -= amount;
+            self.record_outflow(&env, amount);
+        }
+        self.store_lender(&lender, position);
+        if amount > 0 {
+            token::Client::new(&env, &self.pool.token).transfer(&env.current_contract_address(), &lender, &amount);
+        }
+        env.events().publish((symbol_short!("wd_all"), lender.clone()), (amount, locked));
+        log!(&env, "Withdrew {} with {} locked for {}", amount, locked, lender);
+        Ok(amount)
+    }
+
+    /// Pays out the lender's distributed-but-unclaimed interest.
+    pub fn claim_yield(&mut self, env: Env, lender: Address) -> Result<i64, LoanError> {
+        lender.require_auth();
+        let mut position = self.settle_lender(&lender);
+        let amount = position.unclaimed;
+        position.unclaimed = 0;
+        self.store_lender(&lender, position);
+        if amount > 0 {
+            token::Client::new(&env, &self.pool.token).transfer(&env.current_contract_address(), &lender, &amount);
+            log!(&env, "Yield claimed: {} by {}", amount, lender);
+        }
+        Ok(amount)
+    }
+
+    /// Moves `amount` shares from `from` to `to`. Yield distributed before
+    /// the transfer stays with `from`; only future yield follows the shares.
+    pub fn transfer_shares(&mut self, env: Env, from: Address, to: Address, amount: i64) -> Result<(), LoanError> {
+        from.require_auth();
+        if amount <= 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        if from == to {
+            return Err(LoanError::SelfTransfer);
+        }
+        let sender = self.settle_lender(&from);
+        if sender.shares - sender.listed < amount {
+            return Err(LoanError::InsufficientShares);
+        }
+        self.move_shares(&from, &to, amount);
+        log!(&env, "Shares transferred: {} from {} to {}", amount, from, to);
+        Ok(())
+    }
+
+    /// Lists `amount` of the seller's shares for sale at a total `price`.
+    pub fn list_shares(&mut self, env: Env, seller: Address, amount: i64, price: i64) -> Result<u64, LoanError> {
+        seller.require_auth();
+        if amount <= 0 || price <= 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        let mut position = self.settle_lender(&seller);
+        if position.shares - position.listed < amount {
+            return Err(LoanError::InsufficientShares);
+        }
+        position.listed += amount;
+        self.store_lender(&seller, position);
+        let id = self.pool.next_listing_id;
+        self.pool.next_listing_id += 1;
+        self.pool.listings.set(id, ShareListing { seller: seller.clone(), amount, price });
+        log!(&env, "Listing {}: {} shares for {} by {}", id, amount, price, seller);
+        Ok(id)
+    }
+
+    /// Fills a listing: the buyer pays the seller `price` and receives the
+    /// listed shares in the same call.
+    pub fn buy_shares(&mut self, env: Env, buyer: Address, listing_id: u64) -> Result<(), LoanError> {
+        buyer.require_auth();
+        let listing = match self.pool.listings.get(listing_id) {
+            Some(listing) => listing,
+            None => return Err(LoanError::ListingNotFound),
+        };
+        if listing.seller == buyer {
+            return Err(LoanError::SelfTransfer);
+        }
+        token::Client::new(&env, &self.pool.token).transfer(&buyer, &listing.seller, &listing.price);
+        let mut seller = self.settle_lender(&listing.seller);
+        seller.listed -= listing.amount;
+        self.store_lender(&listing.seller, seller);
+        self.move_shares(&listing.seller, &buyer, listing.amount);
+        self.pool.listings.remove(listing_id);
+        log!(&env, "Listing {} bought by {}", listing_id, buyer);
+        Ok(())
+    }
+
+    pub fn cancel_listing(&mut self, env: Env, seller: Address, listing_id: u64) -> Result<(), LoanError> {
+        seller.require_auth();
+        let listing = match self.pool.listings.get(listing_id) {
+            Some(listing) if listing.seller == seller => listing,
+            _ => return Err(LoanError::ListingNotFound),
+        };
+        let mut position = self.settle_lender(&seller);
+        position.listed -= listing.amount;
+        self.store_lender(&seller, position);
+        self.pool.listings.remove(listing_id);
+        log!(&env, "Listing {} cancelled", listing_id);
+        Ok(())
+    }
+
+    /// Shares a lender holds that aren't tied up in listings.
+    pub fn get_available_shares(&self, lender: Address) -> i64 {
+        self.pool.lenders.get(lender).map(|p| p.shares - p.listed).unwrap_or(0)
+    }
+
+    pub fn get_shares(&self, lender: Address) -> i64 {
+        self.pool.lenders.get(lender).map(|p| p.shares).unwrap_or(0)
+    }
+
+    pub fn claimable_yield(&self, lender: Address) -> i64 {
+        self.settle_lender(&lender).unclaimed
+    }
+
+    /// Posts `amount` of the collateral token against an active loan. As the
+    /// principal is paid down, collateral beyond what `max_ltv_bps` requires
+    /// is released back to the borrower.
+    pub fn add_collateral(&mut self, env: Env, borrower: Address, loan_id: u64, amount: i64) -> Result<(), LoanError> {
+        borrower.require_auth();
+        if amount <= 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        let collateral_token = match self.pool.collateral_token.clone() {
+            Some(token) => token,
+            None => return Err(LoanError::CollateralDisabled),
+        };
+        match self.pool.loans.get(loan_id) {
+            Some(mut loan) if loan.borrower == borrower && loan.status == LoanStatus::Active => {
+                token::Client::new(&env, &collateral_token).transfer(&borrower, &env.current_contract_address(), &amount);
+                loan.collateral += amount;
+                self.pool.loans.set(loan_id, loan);
+                log!(&env, "Collateral added: {} for loan {}", amount, loan_id);
+                Ok(())
+            }
+            _ => Err(LoanError::LoanNotFound),
+        }
+    }
+
+    /// Cures part of a delinquent loan out of its collateral, short of
+    /// defaulting it. Collateral is taken at face value: the owner pays the
+    /// pool `amount` of the lending token and receives `amount` of the
+    /// collateral to dispose of, and the payment is booked as principal only.
+    /// Returns the amount applied, at most the outstanding principal.
+    pub fn force_repay_from_collateral(&mut self, env: Env, owner: Address, loan_id: u64, amount: i64) -> Result<i64, LoanError> {
+        self.require_owner(&owner)?;
+        if amount <= 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.status == LoanStatus::Active => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        if amount > loan.collateral {
+            return Err(LoanError::InsufficientCollateral);
+        }
+        let now = env.ledger().timestamp();
+        if !self.delinquent_since(&loan).map_or(false, |since| now > since) {
+            return Err(LoanError::LoanCurrent);
+        }
+        self.accrue(&mut loan, now);
+        let applied = amount.min(Self::principal_outstanding(&loan));
+        if applied == 0 {
+            return Ok(0);
+        }
+        token::Client::new(&env, &self.pool.token).transfer(&owner, &env.current_contract_address(), &applied);
+        let collateral_token = self.pool.collateral_token.clone().unwrap();
+        token::Client::new(&env, &collateral_token).transfer(&env.current_contract_address(), &owner, &applied);
+        loan.collateral -= applied;
+        loan.repaid_amount += applied;
+        let to_blend = applied.min(loan.blend_funded);
+        if to_blend > 0 {
+            self.repay_blend(&env, to_blend);
+            loan.blend_funded -= to_blend;
+        }
+        self.pool.total_funds += applied - to_blend;
+        self.record_event(&env, loan_id, LoanEventKind::Repayment, applied);
+        self.close_if_repaid(&env, &mut loan);
+        if loan.status == LoanStatus::Repaid {
+            self.release_collateral(&env, &mut loan);
+        }
+        self.pool.loans.set(loan_id, loan);
+        log!(&env, "Applied {} collateral to loan {}", applied, loan_id);
+        Ok(applied)
+    }
+
+    /// Sets the collateral new loans must bring, in bps of the loan amount.
+    pub fn set_min_collateral_bps(&mut self, env: Env, owner: Address, bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_num(&env, &owner, "min_collateral_bps", self.pool.min_collateral_bps, bps);
+        self.pool.min_collateral_bps = bps;
+        log!(&env, "Min collateral set to {} bps", bps);
+        Ok(())
+    }
+
+    pub fn set_max_ltv_bps(&mut self, env: Env, owner: Address, bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if bps == 0 || bps as i64 > BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "max_ltv_bps", self.pool.max_ltv_bps, bps);
+        self.pool.max_ltv_bps = bps;
+        log!(&env, "Max LTV set to {} bps", bps);
+        Ok(())
+    }
+
+    /// The contract version, starting at 1 and bumped by every `upgrade`.
+    pub fn version(&self) -> u32 {
+        self.pool.version
+    }
+
+    /// Bitmask of the features this instance has turned on: `CAP_COLLATERAL`
+    /// once a collateral token is set, `CAP_INSURANCE` while any repayments
+    /// fund insurance, `CAP_BLEND` with a Blend pool set, and
+    /// `CAP_GOVERNANCE`, which is always available.
+    pub fn capabilities(&self) -> u32 {
+        let mut caps = CAP_GOVERNANCE;
+        if self.pool.collateral_token.is_some() {
+            caps |= CAP_COLLATERAL;
+        }
+        if self.pool.insurance_funding_bps > 0 || self.pool.interest_insurance_bps > 0 {
+            caps |= CAP_INSURANCE;
+        }
+        if self.pool.blend_pool.is_some() {
+            caps |= CAP_BLEND;
+        }
+        caps
+    }
+
+    /// Swaps in new contract code and bumps `version`. Returns the new
+    /// version.
+    pub fn upgrade(&mut self, env: Env, owner: Address, new_wasm_hash: BytesN<32>) -> Result<u32, LoanError> {
+        self.require_owner(&owner)?;
+        env.deployer().update_current_contract_wasm(new_wasm_hash);
+        self.record_config_num(&env, &owner, "version", self.pool.version, self.pool.version + 1);
+        self.pool.version += 1;
+        log!(&env, "Contract upgraded to version {}", self.pool.version);
+        Ok(self.pool.version)
+    }
+
+    pub fn get_owner(&self) -> Address {
+        self.owner.clone()
+    }
+
+    /// Changes where origination fees are sent.
+    pub fn set_fee_collector(&mut self, env: Env, owner: Address, who: Address) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_change(&env, &owner, "fee_collector", ConfigValue::Addr(self.pool.fee_collector.clone()), ConfigValue::Addr(who.clone()));
+        self.pool.fee_collector = who.clone();
+        log!(&env, "Fee collector set to {}", who);
+        Ok(())
+    }
+
+    pub fn get_config(&self) -> PoolConfig {
+        PoolConfig {
+            base_interest_rate: self.pool.base_interest_rate,
+            min_loan_amount: self.pool.min_loan_amount,
+            max_loan_amount: self.pool.max_loan_amount,
+            origination_fee_bps: self.pool.origination_fee_bps,
+            reserve_bps: self.pool.reserve_bps,
+            savings_bps: self.pool.savings_bps,
+            tapered_savings_bps: self.pool.tapered_savings_bps,
+            reward_threshold: self.pool.reward_threshold,
+            insurance_funding_bps: self.pool.insurance_funding_bps,
+            interest_insurance_bps: self.pool.interest_insurance_bps,
+            interest_yield_bps: self.pool.interest_yield_bps,
+            max_utilization_bps: self.pool.max_utilization_bps,
+            max_ltv_bps: self.pool.max_ltv_bps,
+            min_repayment: self.pool.min_repayment,
+            cooldown_secs: self.pool.cooldown_secs,
+            min_term_secs: self.pool.min_term_secs,
+            max_term_secs: self.pool.max_term_secs,
+            grace_period_secs: self.pool.grace_period_secs,
+            penalty_multiplier_bps: self.pool.penalty_multiplier_bps,
+            min_loan_rate_bps: self.pool.min_loan_rate_bps,
+            early_repayment_rebate_bps: self.pool.early_repayment_rebate_bps,
+            early_repayment_penalty_bps: self.pool.early_repayment_penalty_bps,
+            disbursement_delay_secs: self.pool.disbursement_delay_secs,
+            max_outflow_per_window: self.pool.max_outflow_per_window,
+            outflow_window_secs: self.pool.outflow_window_secs,
+            loyalty_discount_bps: self.pool.loyalty_discount_bps,
+            max_loyalty_discount_bps: self.pool.max_loyalty_discount_bps,
+            rounding_mode: self.pool.rounding_mode,
+            max_loans: self.pool.max_loans,
+            max_active_loans: self.pool.max_active_loans,
+            default_cooldown_secs: self.pool.default_cooldown_secs,
+            compounding_period_secs: self.pool.compounding_period_secs,
+            max_savings_per_loan: self.pool.max_savings_per_loan,
+            promo_until_ts: self.pool.promo_until_ts,
+            promo_grace_secs: self.pool.promo_grace_secs,
+            late_fee_bps: self.pool.late_fee_bps,
+            max_late_fee_bps_of_principal: self.pool.max_late_fee_bps_of_principal,
+            min_collateral_bps: self.pool.min_collateral_bps,
+            payment_application: self.pool.payment_application,
+            variable_rate_loans: self.pool.variable_rate_loans,
+        }
+    }
+
+    /// Parameter changes made by the owner or by governance, oldest first.
+    /// Only the last `MAX_CONFIG_HISTORY` are kept.
+    pub fn get_config_history(&self) -> Vec<ConfigChange> {
+        self.pool.config_history.clone()
+    }
+
+    /// Queues a sensitive parameter change; it can be executed once
+    /// `PARAM_TIMELOCK_SECS` have passed, giving users time to react.
+    pub fn queue_param_change(&mut self, env: Env, owner: Address, change: ParamChange) -> Result<u64, LoanError> {
+        self.require_owner(&owner)?;
+        self.validate_param_change(&change)?;
+        let id = self.pool.next_change_id;
+        let execute_at = env.ledger().timestamp() + PARAM_TIMELOCK_SECS;
+        self.pool.next_change_id += 1;
+        self.pool.queued_changes.set(id, QueuedChange { change, execute_at });
+        log!(&env, "Parameter change {} queued, executable at {}", id, execute_at);
+        Ok(id)
+    }
+
+    pub fn execute_param_change(&mut self, env: Env, owner: Address, id: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        let queued = match self.pool.queued_changes.get(id) {
+            Some(queued) => queued,
+            None => return Err(LoanError::ChangeNotFound),
+        };
+        if env.ledger().timestamp() < queued.execute_at {
+            return Err(LoanError::TimelockActive);
+        }
+        self.validate_param_change(&queued.change)?;
+        self.apply_param_change(&env, &owner, queued.change);
+        self.pool.queued_changes.remove(id);
+        log!(&env, "Parameter change {} executed", id);
+        Ok(())
+    }
+
+    pub fn cancel_param_change(&mut self, env: Env, owner: Address, id: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if self.pool.queued_changes.get(id).is_none() {
+            return Err(LoanError::ChangeNotFound);
+        }
+        self.pool.queued_changes.remove(id);
+        log!(&env, "Parameter change {} cancelled", id);
+        Ok(())
+    }
+
+    /// Sets the shortest and longest loan terms `request_loan` accepts.
+    pub fn set_term_bounds(&mut self, env: Env, owner: Address, min_term_secs: u64, max_term_secs: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if min_term_secs == 0 || min_term_secs > max_term_secs {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "min_term_secs", self.pool.min_term_secs, min_term_secs);
+        self.pool.min_term_secs = min_term_secs;
+        self.record_config_num(&env, &owner, "max_term_secs", self.pool.max_term_secs, max_term_secs);
+        self.pool.max_term_secs = max_term_secs;
+        log!(&env, "Term bounds set: {} to {} seconds", min_term_secs, max_term_secs);
+        Ok(())
+    }
+
+    /// Sets how early repayment is treated: paying a loan off before the end
+    /// of its term waives `rebate_bps` of the unpaid interest, or every
+    /// payment of principal ahead of schedule is charged `penalty_bps` of it
+    /// on top. At most one of the two may be set.
+    pub fn set_early_repayment_terms(&mut self, env: Env, owner: Address, rebate_bps: u32, penalty_bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if (rebate_bps > 0 && penalty_bps > 0) || rebate_bps as i64 > BPS_DENOMINATOR || penalty_bps as i64 > BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "early_repayment_rebate_bps", self.pool.early_repayment_rebate_bps, rebate_bps);
+        self.pool.early_repayment_rebate_bps = rebate_bps;
+        self.record_config_num(&env, &owner, "early_repayment_penalty_bps", self.pool.early_repayment_penalty_bps, penalty_bps);
+        self.pool.early_repayment_penalty_bps = penalty_bps;
+        log!(&env, "Early repayment terms set: {} bps rebate, {} bps penalty", rebate_bps, penalty_bps);
+        Ok(())
+    }
+
+    /// Sets how long after a default the borrower is kept from borrowing,
+    /// whether or not the defaulted loan has been reinstated.
+    pub fn set_default_cooldown_secs(&mut self, env: Env, owner: Address, cooldown_secs: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_num(&env, &owner, "default_cooldown_secs", self.pool.default_cooldown_secs, cooldown_secs);
+        self.pool.default_cooldown_secs = cooldown_secs;
+        log!(&env, "Default cooldown set to {} seconds", cooldown_secs);
+        Ok(())
+    }
+
+    /// Sets how often unpaid interest is folded into the balance it accrues
+    /// on, counted from each loan's origination. Zero means simple interest
+    /// on outstanding principal.
+    pub fn set_compounding_period_secs(&mut self, env: Env, owner: Address, period_secs: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if period_secs != 0 && period_secs < MIN_COMPOUNDING_PERIOD_SECS {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "compounding_period_secs", self.pool.compounding_period_secs, period_secs);
+        self.pool.compounding_period_secs = period_secs;
+        log!(&env, "Compounding period set to {} seconds", period_secs);
+        Ok(())
+    }
+
+    /// Runs an interest-free promotion: loans disbursed before `until_ts`
+    /// accrue nothing for their first `grace_secs`.
+    pub fn set_promo(&mut self, env: Env, owner: Address, until_ts: u64, grace_secs: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_num(&env, &owner, "promo_until_ts", self.pool.promo_until_ts, until_ts);
+        self.pool.promo_until_ts = until_ts;
+        self.record_config_num(&env, &owner, "promo_grace_secs", self.pool.promo_grace_secs, grace_secs);
+        self.pool.promo_grace_secs = grace_secs;
+        log!(&env, "Promo set: {} seconds interest-free for loans before {}", grace_secs, until_ts);
+        Ok(())
+    }
+
+    /// Sets the minimum time a borrower must wait between two originations.
+    pub fn set_cooldown_secs(&mut self, env: Env, owner: Address, cooldown_secs: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_num(&env, &owner, "cooldown_secs", self.pool.cooldown_secs, cooldown_secs);
+        self.pool.cooldown_secs = cooldown_secs;
+        log!(&env, "Origination cooldown set to {} seconds", cooldown_secs);
+        Ok(())
+    }
+
+    /// Sets the highest share of the pool that may be lent out. New loans that
+    /// would push utilization past it are rejected.
+    pub fn set_max_utilization_bps(&mut self, env: Env, owner: Address, bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if bps as i64 > BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "max_utilization_bps", self.pool.max_utilization_bps, bps);
+        self.pool.max_utilization_bps = bps;
+        log!(&env, "Max utilization set to {} bps", bps);
+        Ok(())
+    }
+
+    fn validate_param_change(&self, change: &ParamChange) -> Result<(), LoanError> {
+        match change {
+            ParamChange::LoanLimits(min_amount, max_amount) => {
+                if *min_amount <= 0 || min_amount > max_amount {
+                    return Err(LoanError::InvalidParameter);
+                }
+            }
+            ParamChange::OriginationFeeBps(bps) | ParamChange::ReserveBps(bps) => {
+                if *bps as i64 > BPS_DENOMINATOR {
+                    return Err(LoanError::InvalidParameter);
+                }
+            }
+            ParamChange::CollateralToken(token) => {
+                if self.loans().iter().any(|l| l.collateral > 0) {
+                    return Err(LoanError::CollateralHeld);
+                }
+                if *token == self.pool.token {
+                    return Err(LoanError::InvalidParameter);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_param_change(&mut self, env: &Env, by: &Address, change: ParamChange) {
+        match change {
+            ParamChange::LoanLimits(min_amount, max_amount) => {
+                self.record_config_num(env, by, "min_loan_amount", self.pool.min_loan_amount, min_amount);
+                self.record_config_num(env, by, "max_loan_amount", self.pool.max_loan_amount, max_amount);
+                self.pool.min_loan_amount = min_amount;
+                self.pool.max_loan_amount = max_amount;
+                log!(env, "Loan limits set: {} to {}", min_amount, max_amount);
+            }
+            ParamChange::OriginationFeeBps(bps) => {
+                self.record_config_num(env, by, "origination_fee_bps", self.pool.origination_fee_bps, bps);
+                self.pool.origination_fee_bps = bps;
+                log!(env, "Origination fee set to {} bps", bps);
+            }
+            ParamChange::ReserveBps(bps) => {
+                self.record_config_num(env, by, "reserve_bps", self.pool.reserve_bps, bps);
+                self.pool.reserve_bps = bps;
+                log!(env, "Reserve set to {} bps", bps);
+            }
+            ParamChange::CollateralToken(token) => {
+                log!(env, "Collateral token set to {}", token);
+                self.record_config_change(env, by, "collateral_token", ConfigValue::from(self.pool.collateral_token.clone()), ConfigValue::Addr(token.clone()));
+                self.pool.collateral_token = Some(token);
+            }
+        }
+    }
+
+    /// Disburses an approved loan right away, or parks it in
+    /// `ReadyToDisburse` when a disbursement delay is configured.
+    fn approve_for_disbursement(&mut self, env: &Env, loan: &mut Loan) {
+        loan.approved_at = env.ledger().timestamp();
+        if self.pool.disbursement_delay_secs == 0 {
+            self.activate_loan(env, loan);
+        } else {
+            loan.status = LoanStatus::ReadyToDisburse;
+            log!(env, "Loan {} ready to disburse after {}", loan.id, loan.approved_at + self.pool.disbursement_delay_secs);
+        }
+    }
+
+    /// `origination_fee_bps` less `loyalty_discount_bps` for each loan the
+    /// borrower has fully repaid, up to `max_loyalty_discount_bps`.
+    fn origination_fee_bps_for(&self, borrower: &Address) -> u32 {
+        let repaid = self.pool.repaid_counts.get(borrower.clone()).unwrap_or(0);
+        let discount = repaid.saturating_mul(self.pool.loyalty_discount_bps).min(self.pool.max_loyalty_discount_bps);
+        self.pool.origination_fee_bps.saturating_sub(discount)
+    }
+
+    /// Transfers the loan's funds to the borrower, or its `disburse_to`
+    /// address, and starts it accruing. The origination fee goes to the fee
+    /// collector. Anything the pool can't cover is drawn from Blend and
+    /// recorded as `blend_funded`.
+    fn activate_loan(&mut self, env: &Env, loan: &mut Loan) {
+        let now = env.ledger().timestamp();
+        let fee = self.round_div(loan.amount as i128 * self.origination_fee_bps_for(&loan.borrower) as i128, BPS_DENOMINATOR as i128) as i64;
+        let proceeds = loan.amount - fee;
+        let from_blend = match self.pool.blend_pool.clone() {
+            Some(blend) => {
+                let from_blend = self.blend_draw_for(loan.amount).min(proceeds);
+                if from_blend > 0 {
+                    BlendClient::new(env, &blend).draw(&env.current_contract_address(), &from_blend);
+                }
+                from_blend
+            }
+            None => 0,
+        };
+        loan.blend_funded = from_blend;
+        let token = token::Client::new(env, &self.pool.token);
+        let recipient = loan.disburse_to.clone().unwrap_or(loan.borrower.clone());
+        token.transfer(&env.current_contract_address(), &recipient, &proceeds);
+        if fee > 0 {
+            token.transfer(&env.current_contract_address(), &self.pool.fee_collector, &fee);
+        }
+        self.pool.total_funds -= loan.amount - from_blend;
+        self.record_outflow(env, loan.amount - from_blend);
+        let borrowed = self.pool.borrowed_totals.get(loan.borrower.clone()).unwrap_or(0);
+        self.pool.borrowed_totals.set(loan.borrower.clone(), borrowed + loan.amount);
+        loan.status = LoanStatus::Active;
+        loan.originated_at = now;
+        loan.last_accrual_ts = now;
+        if now < self.pool.promo_until_ts {
+            loan.promo_ends_at = now + self.pool.promo_grace_secs;
+        }
+        self.record_event(env, loan.id, LoanEventKind::Originated, loan.amount);
+        log!(env, "Loan {} disbursed: {} stroops, fee {}", loan.id, loan.amount, fee);
+    }
+
+    /// Adds to the current outflow window's total, starting a new window when
+    /// `outflow_window_secs` have passed. New lending is paused as soon as a
+    /// window's outflow exceeds `max_outflow_per_window`.
+    fn record_outflow(&mut self, env: &Env, amount: i64) {
+        let now = env.ledger().timestamp();
+        if now >= self.pool.outflow_window_start + self.pool.outflow_window_secs {
+            self.pool.outflow_window_start = now;
+            self.pool.outflow_in_window = 0;
+        }
+        self.pool.outflow_in_window += amount;
+        if !self.pool.paused && self.pool.outflow_in_window > self.pool.max_outflow_per_window {
+            self.pool.paused = true;
+            env.events().publish((Symbol::new(env, "circuit_break"),), self.pool.outflow_in_window);
+            log!(env, "Circuit breaker tripped: {} out since {}", self.pool.outflow_in_window, self.pool.outflow_window_start);
+        }
+    }
+
+    /// Idle funds the pool must hold back: `reserve_bps` of lent principal
+    /// plus idle funds.
+    fn required_reserve(&self) -> i64 {
+        let lent = self.loans().iter().filter(|l| l.status == LoanStatus::Active).map(|l| l.amount).sum::<i64>();
+        ((lent + self.pool.total_funds) as i128 * self.pool.reserve_bps as i128 / BPS_DENOMINATOR as i128) as i64
+    }
+
+    /// Share of the pool (lent principal plus idle funds) that would be lent
+    /// out after disbursing a further `extra`.
+    fn utilization_bps_after(&self, extra: i64) -> i64 {
+        let lent = self.loans().iter().filter(|l| l.status == LoanStatus::Active).map(|l| l.amount).sum::<i64>();
+        let assets = lent + self.pool.total_funds;
+        if assets <= 0 {
+            return BPS_DENOMINATOR;
+        }
+        ((lent + extra) as i128 * BPS_DENOMINATOR as i128 / assets as i128) as i64
+    }
+
+    /// Estimated annual yield on `total_funds`, in bps. Interest income is
+    /// bucketed into fixed `APY_WINDOW_SECS` windows; the estimate annualizes
+    /// the income of the last completed window plus the current partial one,
+    /// so it always reflects between one and two windows of history.
+    pub fn pool_apy_bps(&self, env: Env) -> u32 {
+        let now = env.ledger().timestamp();
+        let earned = self.pool.last_window_earned + self.pool.interest_window_earned;
+        let secs = self.pool.last_window_secs + now.saturating_sub(self.pool.interest_window_start);
+        if secs == 0 || earned <= 0 || self.pool.total_funds <= 0 {
+            return 0;
+        }
+        let apy = earned as i128 * BPS_DENOMINATOR as i128 * SECONDS_PER_YEAR as i128
+            / (secs as i128 * self.pool.total_funds as i128);
+        apy.min(u32::MAX as i128) as u32
+    }
+
+    fn record_interest_income(&mut self, now: u64, interest: i64) {
+        if now >= self.pool.interest_window_start + APY_WINDOW_SECS {
+            self.pool.last_window_earned = self.pool.interest_window_earned;
+            self.pool.last_window_secs = now - self.pool.interest_window_start;
+            self.pool.interest_window_start = now;
+            self.pool.interest_window_earned = 0;
+        }
+        self.pool.interest_window_earned += interest;
+    }
+
+    /// Pulls `amount` from `payer` and applies it to the loan `loan_id`:
+    /// accrues interest, splits the payment, updates the pool buckets, and
+    /// releases collateral that's no longer needed.
+    fn apply_repayment(&mut self, env: &Env, payer: &Address, loan_id: u64, amount: i64) -> Result<(), LoanError> {
+        if amount <= 0 {
+            return Err(LoanError::InvalidAmount);
+        }
+        let now = env.ledger().timestamp();
+        let loan = self.pool.loans.get(loan_id).unwrap();
+        let balance = self.remaining_balance(&loan, now);
+        if amount < self.pool.min_repayment && amount < balance {
+            return Err(LoanError::RepaymentBelowMinimum);
+        }
+        let (mut loan, split, penalty) = self.plan_repayment(&loan, amount, now);
+        let rebate = balance - self.remaining_balance(&loan, now);
+        if rebate > 0 {
+            log!(env, "Early repayment rebate of {} on loan {}", rebate, loan_id);
+        }
+        token::Client::new(env, &self.pool.token).transfer(payer, &env.current_contract_address(), &(amount + penalty));
+        if penalty > 0 {
+            // The penalty goes to lenders; with none, it stays in the pool.
+            if !self.distribute_yield(penalty) {
+                self.pool.total_funds += penalty;
+            }
+            log!(env, "Early repayment penalty of {} on loan {}", penalty, loan_id);
+        }
+        let borrower = loan.borrower.clone();
+        loan.interest_paid += split.to_interest;
+        loan.repaid_amount += amount;
+        loan.savings += split.to_savings;
+        let saved = self.pool.savings_totals.get(borrower.clone()).unwrap_or(0);
+        self.pool.savings_totals.set(borrower.clone(), saved + split.to_savings);
+        let rewarded = self.rate_with_reward(&loan, loan.base_rate, now);
+        if rewarded < loan.interest_rate {
+            loan.interest_rate = rewarded;
+            log!(env, "Reward: Interest rate reduced to {} for {}", loan.interest_rate, borrower);
+        }
+        let to_lenders = if self.distribute_yield(split.interest_to_yield) { split.interest_to_yield } else { 0 };
+        // Principal goes back to Blend before the pool, out of what's left
+        // of the payment once savings and interest shares are set aside.
+        let retained = amount - split.to_savings - split.to_insurance - to_lenders - split.interest_to_insurance;
+        let to_blend = retained.min(split.to_principal).min(loan.blend_funded).max(0);
+        if to_blend > 0 {
+            self.repay_blend(env, to_blend);
+            loan.blend_funded -= to_blend;
+        }
+        self.pool.total_funds += retained - to_blend;
+        self.pool.insurance_fund += split.to_insurance + split.interest_to_insurance;
+        self.pool.insurance_from_savings += split.to_insurance;
+        self.record_interest_income(now, split.to_interest);
+        self.record_event(env, loan.id, LoanEventKind::Repayment, amount);
+        self.close_if_repaid(env, &mut loan);
+        self.release_collateral(env, &mut loan);
+        self.pool.loans.set(loan_id, loan);
+        Ok(())
+    }
+
+    /// Marks an active loan `Repaid` once nothing is owed on it: counts it
+    /// toward the borrower's repaid loans, reports it to the credit bureau
+    /// and hands back savings if the borrower opted in.
+    fn close_if_repaid(&mut self, env: &Env, loan: &mut Loan) {
+        let borrower = loan.borrower.clone();
+        if loan.status == LoanStatus::Active && loan.repaid_amount >= loan.amount + loan.accrued_interest {
+            loan.status = LoanStatus::Repaid;
+            let repaid = self.pool.repaid_counts.get(borrower.clone()).unwrap_or(0);
+            self.pool.repaid_counts.set(borrower.clone(), repaid + 1);
+            self.record_event(env, loan.id, LoanEventKind::Closed, loan.repaid_amount);
+            self.report_to_bureau(env, &borrower, loan.id, false);
+            log!(env, "Loan fully repaid by {}", borrower);
+            if loan.savings > 0 && self.pool.auto_return_savings.get(borrower.clone()).unwrap_or(false) {
+                token::Client::new(env, &self.pool.token).transfer(&env.current_contract_address(), &borrower, &loan.savings);
+                log!(env, "Returned {} savings to {}", loan.savings, borrower);
+                loan.savings = 0;
+            }
+        }
+    }
+
+    /// Returns collateral the loan no longer needs to its borrower.
+    fn release_collateral(&self, env: &Env, loan: &mut Loan) {
+        let release = self.releasable_collateral(loan);
+        if release > 0 {
+            loan.collateral -= release;
+            let collateral_token = self.pool.collateral_token.clone().unwrap();
+            token::Client::new(env, &collateral_token).transfer(&env.current_contract_address(), &loan.borrower, &release);
+            log!(env, "Released {} collateral to {}", release, loan.borrower);
+        }
+    }
+
+    fn repay_blend(&self, env: &Env, amount: i64) {
+        let blend = self.pool.blend_pool.clone().unwrap();
+        token::Client::new(env, &self.pool.token).transfer(&env.current_contract_address(), &blend, &amount);
+        BlendClient::new(env, &blend).repay(&env.current_contract_address(), &amount);
+    }
+
+    fn is_repeat_repayment(&self, borrower: &Address, key: &BytesN<32>, now: u64) -> bool {
+        match self.pool.repayment_keys.get(borrower.clone()) {
+            Some(keys) => keys.iter().any(|(k, ts)| k == *key && now < ts + IDEMPOTENCY_RETENTION_SECS),
+            None => false,
+        }
+    }
+
+    /// Remembers a repayment key, dropping expired keys and keeping at most
+    /// `MAX_IDEMPOTENCY_KEYS` of the newest per borrower.
+    fn record_repayment_key(&mut self, env: &Env, borrower: &Address, key: BytesN<32>, now: u64) {
+        let mut kept = Vec::new(env);
+        if let Some(keys) = self.pool.repayment_keys.get(borrower.clone()) {
+            for (k, ts) in keys.iter() {
+                if now < ts + IDEMPOTENCY_RETENTION_SECS {
+                    kept.push_back((k, ts));
+                }
+            }
+        }
+        while kept.len() >= MAX_IDEMPOTENCY_KEYS {
+            kept.pop_front();
+        }
+        kept.push_back((key, now));
+        self.pool.repayment_keys.set(borrower.clone(), kept);
+    }
+
+    /// A loan's stored history, oldest first. Only the newest
+    /// `MAX_LOAN_EVENTS` entries are kept.
+    pub fn get_loan_events(&self, env: Env, loan_id: u64) -> Vec<LoanEvent> {
+        self.pool.loan_events.get(loan_id).unwrap_or(Vec::new(&env))
+    }
+
+    fn record_event(&mut self, env: &Env, loan_id: u64, kind: LoanEventKind, amount: i64) {
+        let mut events = self.pool.loan_events.get(loan_id).unwrap_or(Vec::new(env));
+        while events.len() >= MAX_LOAN_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(LoanEvent { ts: env.ledger().timestamp(), kind, amount });
+        self.pool.loan_events.set(loan_id, events);
+    }
+
+    /// Appends to `config_history`, dropping the oldest entries past
+    /// `MAX_CONFIG_HISTORY`, and publishes the change as a `config` event.
+    fn record_config_change(&mut self, env: &Env, by: &Address, param: &str, old: ConfigValue, new: ConfigValue) {
+        let change = ConfigChange { ts: env.ledger().timestamp(), param: Symbol::new(env, param), old, new, by: by.clone() };
+        while self.pool.config_history.len() >= MAX_CONFIG_HISTORY {
+            self.pool.config_history.pop_front();
+        }
+        env.events().publish((symbol_short!("config"), change.param.clone()), change.clone());
+        self.pool.config_history.push_back(change);
+    }
+
+    fn record_config_num(&mut self, env: &Env, by: &Address, param: &str, old: impl Into<i128>, new: impl Into<i128>) {
+        self.record_config_change(env, by, param, ConfigValue::Num(old.into()), ConfigValue::Num(new.into()));
+    }
+
+    /// Spreads `amount` over all shares. Returns false (and distributes
+    /// nothing) when there are no shares to credit.
+    fn distribute_yield(&mut self, amount: i64) -> bool {
+        if amount <= 0 || self.pool.total_shares <= 0 {
+            return false;
+        }
+        self.pool.yield_per_share += amount as i128 * YIELD_PRECISION / self.pool.total_shares as i128;
+        true
+    }
+
+    /// Returns the lender's position with yield distributed so far moved
+    /// into `unclaimed`. Must be followed by `store_lender`.
+    fn settle_lender(&self, lender: &Address) -> LenderPosition {
+        let mut position = self.pool.lenders.get(lender.clone()).unwrap_or(LenderPosition {
+            shares: 0,
+            reward_debt: 0,
+            unclaimed: 0,
+            listed: 0,
+        });
+        let earned = position.shares as i128 * self.pool.yield_per_share / YIELD_PRECISION - position.reward_debt;
+        position.unclaimed += earned as i64;
+        position
+    }
+
+    fn store_lender(&mut self, lender: &Address, mut position: LenderPosition) {
+        position.reward_debt = position.shares as i128 * self.pool.yield_per_share / YIELD_PRECISION;
+        self.pool.lenders.set(lender.clone(), position);
+    }
+
+    /// Moves shares between two lenders after settling both, so yield up to
+    /// now stays with `from`.
+    fn move_shares(&mut self, from: &Address, to: &Address, amount: i64) {
+        let mut sender = self.settle_lender(from);
+        let mut recipient = self.settle_lender(to);
+        sender.shares -= amount;
+        recipient.shares += amount;
+        self.store_lender(from, sender);
+        self.store_lender(to, recipient);
+    }
+
+    fn principal_outstanding(loan: &Loan) -> i64 {
+        (loan.amount - (loan.repaid_amount - loan.interest_paid)).max(0)
+    }
+
+    /// Collateral held beyond what the loan's outstanding principal requires
+    /// at `max_ltv_bps`. All of it is releasable once the loan is repaid,
+    /// none of it while the loan is in default.
+    fn releasable_collateral(&self, loan: &Loan) -> i64 {
+        match loan.status {
+            LoanStatus::Active => {}
+            LoanStatus::Repaid => return loan.collateral,
+            _ => return 0,
+        }
+        let principal = Self::principal_outstanding(loan) as i128;
+        let ltv = self.pool.max_ltv_bps as i128;
+        let required = ((principal * BPS_DENOMINATOR as i128 + ltv - 1) / ltv) as i64;
+        (loan.collateral - required).max(0)
+    }
+
+    /// Works out a repayment of `amount` at `now` for both `apply_repayment`
+    /// and `preview_repayment`: the loan with interest accrued and, if the
+    /// payment closes it early, the rebate taken off; the split of the
+    /// payment; and the penalty charged on top for principal paid ahead of
+    /// schedule.
+    fn plan_repayment(&self, loan: &Loan, amount: i64, now: u64) -> (Loan, RepaymentSplit, i64) {
+        let mut loan = loan.clone();
+        let balance = self.remaining_balance(&loan, now);
+        let rebate = self.early_repayment_rebate(&loan, now);
+        self.accrue(&mut loan, now);
+        // The rebate only comes off the payment that closes the loan early.
+        if rebate > 0 && amount >= balance - rebate {
+            loan.accrued_interest -= rebate;
+        }
+        let split = self.split_repayment(&loan, amount, now);
+        let penalty = self.prepayment_penalty(&loan, split.to_principal, now);
+        (loan, split, penalty)
+    }
+
+    /// The part of the unpaid interest waived for paying the loan off at
+    /// `now`, before the end of its term.
+    fn early_repayment_rebate(&self, loan: &Loan, now: u64) -> i64 {
+        if now >= loan.originated_at + loan.term_secs {
+            return 0;
+        }
+        let unpaid = (loan.accrued_interest + self.pending_interest(loan, now) - loan.interest_paid).max(0);
+        (unpaid as i128 * self.pool.early_repayment_rebate_bps as i128 / BPS_DENOMINATOR as i128) as i64
+    }
+
+    /// The penalty on a payment putting `to_principal` towards the loan at
+    /// `now`: `early_repayment_penalty_bps` of whatever part of it takes the
+    /// principal repaid past what the schedule calls for by then.
+    fn prepayment_penalty(&self, loan: &Loan, to_principal: i64, now: u64) -> i64 {
+        let scheduled = Self::scheduled_principal(loan, now);
+        let repaid = loan.repaid_amount - loan.interest_paid;
+        let to_principal = to_principal.min(Self::principal_outstanding(loan));
+        let prepaid = (repaid + to_principal - scheduled).max(0) - (repaid - scheduled).max(0);
+        (prepaid as i128 * self.pool.early_repayment_penalty_bps as i128 / BPS_DENOMINATOR as i128) as i64
+    }
+
+    /// Principal due under the installment schedule by `now`: all of it once
+    /// the term is up.
+    fn scheduled_principal(loan: &Loan, now: u64) -> i64 {
+        let count = Self::installment_count(loan);
+        let elapsed = now.saturating_sub(loan.originated_at);
+        let due = if elapsed >= loan.term_secs { count } else { (elapsed / INSTALLMENT_PERIOD_SECS).min(count) };
+        (loan.amount as i128 * due as i128 / count as i128) as i64
+    }
+
+    /// Principal plus all interest accrued up to `now`, less what's been repaid.
+    fn remaining_balance(&self, loan: &Loan, now: u64) -> i64 {
+        (loan.amount + loan.accrued_interest + self.pending_interest(loan, now) - loan.repaid_amount).max(0)
+    }
+
+    /// Sets the pool base rate, in bps, and moves every open variable-rate
+    /// loan by the same amount. Fixed-rate loans keep their rate.
+    pub fn set_base_rate(&mut self, env: Env, owner: Address, new_bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if new_bps as i64 > BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.update_base_rate(&env, &owner, new_bps);
+        Ok(())
+    }
+
+    /// Whether loans originated from now on are variable-rate, following
+    /// changes to the base rate, or keep the rate they were priced at.
+    pub fn set_variable_rate_loans(&mut self, env: Env, owner: Address, enabled: bool) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        self.record_config_change(&env, &owner, "variable_rate_loans", ConfigValue::Flag(self.pool.variable_rate_loans), ConfigValue::Flag(enabled));
+        self.pool.variable_rate_loans = enabled;
+        log!(&env, "Variable-rate loans set to {}", enabled);
+        Ok(())
+    }
+
+    /// Applies a new base rate and reprices variable-rate loans, disbursed or
+    /// not, by the change, booking interest at the old rate up to now.
+    fn update_base_rate(&mut self, env: &Env, by: &Address, new_bps: u32) {
+        let old_bps = (self.pool.base_interest_rate * 100.0).round() as i128;
+        self.record_config_num(env, by, "base_interest_rate", old_bps, new_bps);
+        let new_rate = new_bps as f32 / 100.0;
+        let delta = new_rate - self.pool.base_interest_rate;
+        self.pool.base_interest_rate = new_rate;
+        let now = env.ledger().timestamp();
+        let open = |l: &Loan| matches!(l.status, LoanStatus::Pending | LoanStatus::ReadyToDisburse | LoanStatus::Active);
+        for mut loan in self.loans().iter().filter(|l| open(l) && l.variable_rate) {
+            // Loans not yet disbursed have nothing to book; they just
+            // disburse at the new rate.
+            if loan.status == LoanStatus::Active {
+                self.accrue(&mut loan, now);
+            }
+            let base_rate = (loan.base_rate + delta).max(0.0);
+            loan.interest_rate = self.rate_with_reward(&loan, base_rate, now);
+            loan.base_rate = base_rate;
+            self.pool.loans.set(loan.id, loan);
+        }
+        log!(env, "Base rate set to {} bps", new_bps);
+    }
+
+    /// Opens a lender vote on a new base rate, in bps. Only share holders
+    /// may propose; voting runs for `VOTING_PERIOD_SECS`.
+    pub fn propose_base_rate(&mut self, env: Env, proposer: Address, new_bps: u32) -> Result<u64, LoanError> {
+        proposer.require_auth();
+        if self.get_shares(proposer.clone()) <= 0 {
+            return Err(LoanError::InsufficientShares);
+        }
+        if new_bps as i64 > BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        let id = self.pool.next_proposal_id;
+        let ends_at = env.ledger().timestamp() + VOTING_PERIOD_SECS;
+        self.pool.next_proposal_id += 1;
+        let mut weights = Map::new(&env);
+        for (lender, position) in self.pool.lenders.iter() {
+            if position.shares > 0 {
+                weights.set(lender, position.shares);
+            }
+        }
+        self.pool.rate_proposals.set(id, RateProposal {
+            new_bps,
+            votes_for: 0,
+            votes_against: 0,
+            voters: Vec::new(&env),
+            ends_at,
+            weights,
+            total_weight: self.pool.total_shares,
+        });
+        log!(&env, "Rate proposal {}: {} bps by {}, voting until {}", id, new_bps, proposer, ends_at);
+        Ok(id)
+    }
+
+    /// Casts the shares the voter held when the proposal was opened for or
+    /// against it. Each address votes once per proposal.
+    pub fn vote(&mut self, env: Env, voter: Address, proposal_id: u64, support: bool) -> Result<(), LoanError> {
+        voter.require_auth();
+        let mut proposal = match self.pool.rate_proposals.get(proposal_id) {
+            Some(proposal) => proposal,
+            None => return Err(LoanError::ProposalNotFound),
+        };
+        if env.ledger().timestamp() >= proposal.ends_at {
+            return Err(LoanError::VotingClosed);
+        }
+        if proposal.voters.contains(&voter) {
+            return Err(LoanError::AlreadyVoted);
+        }
+        let weight = proposal.weights.get(voter.clone()).unwrap_or(0);
+        if weight <= 0 {
+            return Err(LoanError::InsufficientShares);
+        }
+        if support {
+            proposal.votes_for += weight;
+        } else {
+            proposal.votes_against += weight;
+        }
+        proposal.voters.push_back(voter.clone());
+        self.pool.rate_proposals.set(proposal_id, proposal);
+        log!(&env, "Vote on proposal {} by {}: {} with {} shares", proposal_id, voter, support, weight);
+        Ok(())
+    }
+
+    /// Closes a rate proposal once its voting period is over. It passes, and
+    /// the base rate is updated, if at least `VOTE_QUORUM_BPS` of all shares
+    /// at the time it was opened voted and a majority of them voted for it.
+    /// Returns whether it passed.
+    pub fn finalize_proposal(&mut self, env: Env, proposal_id: u64) -> Result<bool, LoanError> {
+        let proposal = match self.pool.rate_proposals.get(proposal_id) {
+            Some(proposal) => proposal,
+            None => return Err(LoanError::ProposalNotFound),
+        };
+        if env.ledger().timestamp() < proposal.ends_at {
+            return Err(LoanError::VotingOpen);
+        }
+        let turnout = (proposal.votes_for + proposal.votes_against) as i128;
+        let quorum = proposal.total_weight as i128 * VOTE_QUORUM_BPS as i128 / BPS_DENOMINATOR as i128;
+        let passed = turnout >= quorum && proposal.votes_for > proposal.votes_against;
+        if passed {
+            self.update_base_rate(&env, &env.current_contract_address(), proposal.new_bps);
+            log!(&env, "Rate proposal {} passed: base rate {} bps", proposal_id, proposal.new_bps);
+        } else {
+            log!(&env, "Rate proposal {} failed", proposal_id);
+        }
+        self.pool.rate_proposals.remove(proposal_id);
+        Ok(passed)
+    }
+
+    /// Sets the late fee charged by `apply_late_fee`, as bps of principal, and
+    /// the cap on a loan's cumulative late fees, also in bps of principal.
+    pub fn set_late_fee_terms(&mut self, env: Env, owner: Address, late_fee_bps: u32, max_late_fee_bps_of_principal: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if max_late_fee_bps_of_principal as i64 > BPS_DENOMINATOR || late_fee_bps > max_late_fee_bps_of_principal {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "late_fee_bps", self.pool.late_fee_bps, late_fee_bps);
+        self.pool.late_fee_bps = late_fee_bps;
+        self.record_config_num(&env, &owner, "max_late_fee_bps_of_principal", self.pool.max_late_fee_bps_of_principal, max_late_fee_bps_of_principal);
+        self.pool.max_late_fee_bps_of_principal = max_late_fee_bps_of_principal;
+        log!(&env, "Late fee terms set: {} bps, capped at {} bps", late_fee_bps, max_late_fee_bps_of_principal);
+        Ok(())
+    }
+
+    /// Sets the grace period after a missed installment and the multiplier
+    /// applied to a loan's rate while it's overdue beyond that grace.
+    pub fn set_penalty_terms(&mut self, env: Env, owner: Address, grace_period_secs: u64, penalty_multiplier_bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if (penalty_multiplier_bps as i64) < BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "grace_period_secs", self.pool.grace_period_secs, grace_period_secs);
+        self.pool.grace_period_secs = grace_period_secs;
+        self.record_config_num(&env, &owner, "penalty_multiplier_bps", self.pool.penalty_multiplier_bps, penalty_multiplier_bps);
+        self.pool.penalty_multiplier_bps = penalty_multiplier_bps;
+        log!(&env, "Penalty terms set: {} seconds grace, {} bps", grace_period_secs, penalty_multiplier_bps);
+        Ok(())
+    }
+
+    /// Hands an active loan over to a new borrower, who takes on the balance
+    /// and any posted collateral, and must pass the same borrower checks as a
+    /// new loan. Savings built up so far are paid out to the old borrower.
+    /// Needs both borrowers and the owner to sign.
+    /// With `reprice`, interest is booked at the old rate up to now and the
+    /// loan moves to the rate `to` would be offered today.
+    pub fn transfer_loan(&mut self, env: Env, from: Address, to: Address, loan_id: u64, reprice: bool) -> Result<(), LoanError> {
+        from.require_auth();
+        to.require_auth();
+        self.owner.require_auth();
+        if from == to {
+            return Err(LoanError::SelfTransfer);
+        }
+        self.check_borrower(&env, &to)?;
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.borrower == from && loan.status == LoanStatus::Active => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        if loan.savings > 0 {
+            token::Client::new(&env, &self.pool.token).transfer(&env.current_contract_address(), &from, &loan.savings);
+            log!(&env, "Returned {} savings to {}", loan.savings, from);
+            loan.savings = 0;
+        }
+        if reprice {
+            let now = env.ledger().timestamp();
+            self.accrue(&mut loan, now);
+            let base_rate = self.offered_rate(&env, &to);
+            loan.interest_rate = self.rate_with_reward(&loan, base_rate, now);
+            loan.base_rate = base_rate;
+        }
+        loan.borrower = to.clone();
+        log!(&env, "Loan {} transferred from {} to {} at {}", loan_id, from, to, loan.interest_rate);
+        self.pool.loans.set(loan_id, loan);
+        Ok(())
+    }
+
+    /// Lets a borrower move their active variable-rate loan to the rate they'd
+    /// be offered today, at current utilization and credit score, with their
+    /// savings reward reapplied unless it's being held back. Fixed-rate loans
+    /// keep the rate they were priced at. Interest is booked at the old rate
+    /// up to now. Only a strictly lower rate is accepted; returns how many
+    /// points it dropped.
+    pub fn claim_better_rate(&mut self, env: Env, borrower: Address, loan_id: u64) -> Result<f32, LoanError> {
+        borrower.require_auth();
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.borrower == borrower && loan.status == LoanStatus::Active && loan.variable_rate => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        let now = env.ledger().timestamp();
+        let base_rate = self.offered_rate(&env, &borrower);
+        let rate = self.rate_with_reward(&loan, base_rate, now);
+        if rate >= loan.interest_rate {
+            return Err(LoanError::RateNotImproved);
+        }
+        self.accrue(&mut loan, now);
+        let delta = loan.interest_rate - rate;
+        loan.base_rate = base_rate;
+        loan.interest_rate = rate;
+        log!(&env, "Loan {} repriced to {} at borrower's request", loan_id, rate);
+        self.pool.loans.set(loan_id, loan);
+        Ok(delta)
+    }
+
+    /// Writes a loan off as defaulted once it's past the grace period of a
+    /// missed installment. Interest is booked up to now and then stops; the
+    /// borrower is blacklisted and loses `DEFAULT_SCORE_PENALTY` credit score.
+    pub fn mark_default(&mut self, env: Env, owner: Address, loan_id: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.status == LoanStatus::Active => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        let now = env.ledger().timestamp();
+        if !self.delinquent_since(&loan).map_or(false, |since| now > since) {
+            return Err(LoanError::LoanCurrent);
+        }
+        self.accrue(&mut loan, now);
+        loan.status = LoanStatus::Defaulted;
+        let borrower = loan.borrower.clone();
+        let owed = self.remaining_balance(&loan, now);
+        self.pool.loans.set(loan_id, loan);
+        if !self.pool.blacklist.contains(&borrower) {
+            self.pool.blacklist.push_back(borrower.clone());
+        }
+        self.pool.defaulted_at.set(borrower.clone(), now);
+        let score = self.get_credit_score(borrower.clone());
+        self.pool.credit_scores.set(borrower.clone(), score.saturating_sub(DEFAULT_SCORE_PENALTY));
+        self.record_event(&env, loan_id, LoanEventKind::Defaulted, owed);
+        self.report_to_bureau(&env, &borrower, loan_id, true);
+        log!(&env, "Loan {} defaulted with {} owed by {}", loan_id, owed, borrower);
+        Ok(())
+    }
+
+    /// Closes a defaulted loan whose balance has since been paid in full,
+    /// taking the borrower off the blacklist and giving back
+    /// `REINSTATE_SCORE_RECOVERY` of their credit score.
+    pub fn reinstate_loan(&mut self, env: Env, owner: Address, loan_id: u64) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) => loan,
+            None => return Err(LoanError::LoanNotFound),
+        };
+        if loan.status != LoanStatus::Defaulted {
+            return Err(LoanError::LoanNotDefaulted);
+        }
+        if self.remaining_balance(&loan, env.ledger().timestamp()) > 0 {
+            return Err(LoanError::BalanceOutstanding);
+        }
+        loan.status = LoanStatus::Repaid;
+        let borrower = loan.borrower.clone();
+        let release = loan.collateral;
+        loan.collateral = 0;
+        self.pool.loans.set(loan_id, loan);
+        if release > 0 {
+            let collateral_token = self.pool.collateral_token.clone().unwrap();
+            token::Client::new(&env, &collateral_token).transfer(&env.current_contract_address(), &borrower, &release);
+        }
+        if let Some(i) = self.pool.blacklist.first_index_of(&borrower) {
+            self.pool.blacklist.remove(i);
+        }
+        let score = self.get_credit_score(borrower.clone());
+        self.pool.credit_scores.set(borrower.clone(), (score + REINSTATE_SCORE_RECOVERY).min(MAX_CREDIT_SCORE));
+        self.record_event(&env, loan_id, LoanEventKind::Reinstated, 0);
+        log!(&env, "Loan {} reinstated for {}", loan_id, borrower);
+        Ok(())
+    }
+
+    /// Closes an active or defaulted loan for an agreed `settlement_amount`,
+    /// paid by the borrower. Whatever is still owed beyond the settlement is
+    /// forgiven; any Blend draw on the loan is paid back in full, with the
+    /// pool absorbing the difference. The credit bureau is told the loan
+    /// closed in default if anything was forgiven.
+    pub fn close_and_settle(&mut self, env: Env, owner: Address, loan_id: u64, settlement_amount: i64) -> Result<i64, LoanError> {
+        self.require_owner(&owner)?;
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.status == LoanStatus::Active || loan.status == LoanStatus::Defaulted => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        let now = env.ledger().timestamp();
+        self.accrue(&mut loan, now);
+        let owed = self.remaining_balance(&loan, now);
+        if settlement_amount < 0 || settlement_amount > owed {
+            return Err(LoanError::InvalidAmount);
+        }
+        let borrower = loan.borrower.clone();
+        if settlement_amount > 0 {
+            token::Client::new(&env, &self.pool.token).transfer(&borrower, &env.current_contract_address(), &settlement_amount);
+        }
+        if loan.blend_funded > 0 {
+            self.repay_blend(&env, loan.blend_funded);
+        }
+        self.pool.total_funds += settlement_amount - loan.blend_funded;
+        loan.blend_funded = 0;
+        loan.repaid_amount += settlement_amount;
+        loan.status = LoanStatus::Repaid;
+        let forgiven = owed - settlement_amount;
+        let repaid = loan.repaid_amount;
+        let release = loan.collateral;
+        loan.collateral = 0;
+        self.pool.loans.set(loan_id, loan);
+        if release > 0 {
+            let collateral_token = self.pool.collateral_token.clone().unwrap();
+            token::Client::new(&env, &collateral_token).transfer(&env.current_contract_address(), &borrower, &release);
+        }
+        self.record_event(&env, loan_id, LoanEventKind::Closed, repaid);
+        self.report_to_bureau(&env, &borrower, loan_id, forgiven > 0);
+        env.events().publish((symbol_short!("settled"), loan_id), (settlement_amount, forgiven));
+        log!(&env, "Loan {} settled for {} with {} forgiven", loan_id, settlement_amount, forgiven);
+        Ok(forgiven)
+    }
+
+    /// Drops a repaid or defaulted loan, and its event history, from storage
+    /// once it has been inactive for `ARCHIVE_RETENTION_SECS`. The full
+    /// record is published in an `archived` event and returned. Loans still
+    /// holding savings or collateral are kept until those are paid out.
+    pub fn archive_loan(&mut self, env: Env, owner: Address, loan_id: u64) -> Result<Loan, LoanError> {
+        self.require_owner(&owner)?;
+        let loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.status == LoanStatus::Repaid || loan.status == LoanStatus::Defaulted => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        if loan.savings > 0 || loan.collateral > 0 {
+            return Err(LoanError::BalanceOutstanding);
+        }
+        let inactive_since = match self.pool.loan_events.get(loan_id).and_then(|events| events.last()) {
+            Some(event) => event.ts.max(loan.last_accrual_ts),
+            None => loan.last_accrual_ts,
+        };
+        if env.ledger().timestamp() < inactive_since + ARCHIVE_RETENTION_SECS {
+            return Err(LoanError::RetentionPeriodActive);
+        }
+        self.pool.loans.remove(loan_id);
+        self.pool.loan_events.remove(loan_id);
+        env.events().publish((symbol_short!("archived"), loan_id), loan.clone());
+        log!(&env, "Loan {} archived", loan_id);
+        Ok(loan)
+    }
+
+    /// Charges a late fee of `late_fee_bps` of principal on a loan past the
+    /// grace period of a missed installment, at most once per installment.
+    /// The fee is owed like interest; a loan's cumulative `accrued_fees` never
+    /// exceed `max_late_fee_bps_of_principal` of principal. Returns the fee
+    /// charged.
+    pub fn apply_late_fee(&mut self, env: Env, owner: Address, loan_id: u64) -> Result<i64, LoanError> {
+        self.require_owner(&owner)?;
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.status == LoanStatus::Active => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        let now = env.ledger().timestamp();
+        if !self.delinquent_since(&loan).map_or(false, |since| now > since) {
+            return Err(LoanError::LoanCurrent);
+        }
+        // The fee is for the latest installment past its grace period.
+        let installment = self.installments_past_grace(&loan, now);
+        if installment <= loan.late_fee_installment {
+            return Err(LoanError::LateFeeAlreadyCharged);
+        }
+        let principal = (loan.amount - loan.capitalized_interest) as i128;
+        let cap = (principal * self.pool.max_late_fee_bps_of_principal as i128 / BPS_DENOMINATOR as i128) as i64;
+        let fee = ((principal * self.pool.late_fee_bps as i128 / BPS_DENOMINATOR as i128) as i64)
+            .min((cap - loan.accrued_fees).max(0));
+        // Book pending interest first so the fee doesn't change what accrues on it.
+        self.accrue(&mut loan, now);
+        loan.accrued_interest += fee;
+        loan.accrued_fees += fee;
+        loan.late_fee_installment = installment;
+        self.pool.loans.set(loan_id, loan);
+        log!(&env, "Late fee of {} charged on loan {}", fee, loan_id);
+        Ok(fee)
+    }
+
+    /// Folds a delinquent loan's accrued-but-unpaid interest into its
+    /// principal, so it keeps accruing on the full amount owed. The loan must
+    /// be past the grace period of a missed installment. Total capitalized
+    /// interest is capped at `max_capitalization_bps` of the original
+    /// principal.
+    pub fn capitalize_interest(&mut self, env: Env, owner: Address, loan_id: u64) -> Result<i64, LoanError> {
+        self.require_owner(&owner)?;
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) if loan.status == LoanStatus::Active => loan,
+            _ => return Err(LoanError::LoanNotFound),
+        };
+        let now = env.ledger().timestamp();
+        if !self.delinquent_since(&loan).map_or(false, |since| now > since) {
+            return Err(LoanError::LoanCurrent);
+        }
+        self.accrue(&mut loan, now);
+        let unpaid = loan.accrued_interest - loan.interest_paid;
+        if unpaid <= 0 {
+            return Err(LoanError::NothingToCapitalize);
+        }
+        let original = loan.amount - loan.capitalized_interest;
+        let cap = original * self.pool.max_capitalization_bps as i64 / BPS_DENOMINATOR;
+        if loan.capitalized_interest + unpaid > cap {
+            return Err(LoanError::CapitalizationCapExceeded);
+        }
+        loan.amount += unpaid;
+        loan.accrued_interest -= unpaid;
+        loan.capitalized_interest += unpaid;
+        self.pool.loans.set(loan_id, loan);
+        self.record_event(&env, loan_id, LoanEventKind::Capitalized, unpaid);
+        env.events().publish((symbol_short!("cap_int"), loan_id), unpaid);
+        Ok(unpaid)
+    }
+
+    /// Books pending interest on up to `limit` loans starting at position
+    /// `start` in the book (in id order), so the owner or keeper can keep
+    /// every loan current without a call per loan. Only active loans are
+    /// touched; returns how many were. Calling it again in the same ledger
+    /// accrues nothing further.
+    pub fn batch_accrue(&mut self, env: Env, caller: Address, start: u32, limit: u32) -> Result<u32, LoanError> {
+        caller.require_auth();
+        if caller != self.owner && self.pool.keeper.as_ref() != Some(&caller) {
+            return Err(LoanError::Unauthorized);
+        }
+        let now = env.ledger().timestamp();
+        let ids = self.pool.loans.keys();
+        let end = start.saturating_add(limit).min(ids.len());
+        let mut accrued = 0u32;
+        for index in start..end {
+            let id = ids.get(index).unwrap();
+            let mut loan = self.pool.loans.get(id).unwrap();
+            if loan.status != LoanStatus::Active {
+                continue;
+            }
+            self.accrue(&mut loan, now);
+            self.pool.loans.set(id, loan);
+            accrued += 1;
+        }
+        log!(&env, "Accrued interest on {} loans from {}", accrued, start);
+        Ok(accrued)
+    }
+
+    /// Starts time-based accrual on a loan recorded before it existed, which
+    /// isn't `accruing` and stays at its booked balance until migrated.
+    /// Interest runs from now on, never back to origination. Returns false,
+    /// changing nothing, for loans that already accrue.
+    pub fn migrate_loan_to_accrual(&mut self, env: Env, owner: Address, loan_id: u64) -> Result<bool, LoanError> {
+        self.require_owner(&owner)?;
+        let mut loan = match self.pool.loans.get(loan_id) {
+            Some(loan) => loan,
+            None => return Err(LoanError::LoanNotFound),
+        };
+        if loan.accruing {
+            return Ok(false);
+        }
+        let now = env.ledger().timestamp();
+        loan.accruing = true;
+        loan.last_accrual_ts = now;
+        self.pool.loans.set(loan_id, loan);
+        log!(&env, "Loan {} migrated to accrual from {}", loan_id, now);
+        Ok(true)
+    }
+
+    /// Moves interest pending since `last_accrual_ts` into `accrued_interest`,
+    /// keeping the part too small to book in `accrual_carry`.
+    fn accrue(&self, loan: &mut Loan, now: u64) {
+        let (interest, carry, period_interest) = self.interest_accrual(loan, now);
+        loan.accrued_interest += interest;
+        loan.accrual_carry = carry;
+        loan.period_interest = period_interest;
+        loan.last_accrual_ts = now;
+    }
+
+    /// Sets the rate floor stamped on new loans; savings rewards never take
+    /// a loan's rate below its floor.
+    pub fn set_min_loan_rate_bps(&mut self, env: Env, owner: Address, bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if bps as i64 > BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "min_loan_rate_bps", self.pool.min_loan_rate_bps, bps);
+        self.pool.min_loan_rate_bps = bps;
+        log!(&env, "Min loan rate set to {} bps", bps);
+        Ok(())
+    }
+
+    pub fn set_max_capitalization_bps(&mut self, env: Env, owner: Address, bps: u32) -> Result<(), LoanError> {
+        self.require_owner(&owner)?;
+        if bps as i64 > BPS_DENOMINATOR {
+            return Err(LoanError::InvalidParameter);
+        }
+        self.record_config_num(&env, &owner, "max_capitalization_bps", self.pool.max_capitalization_bps, bps);
+        self.pool.max_capitalization_bps = bps;
+        log!(&env, "Max capitalization set to {} bps", bps);
+        Ok(())
+    }
+
+    /// Installment plan for a loan as `(due_ts, payment, principal_portion,
+    /// interest_portion)`, assuming every installment is paid on its due date
+    /// at the loan's current rate.
+    pub fn amortization_schedule(&self, env: Env, loan_id: u64) -> Result<Vec<(u64, i64, i64, i64)>, LoanError> {
+        let loan = match self.pool.loans.get(loan_id) {
+            Some(loan) => loan,
+            None => return Err(LoanError::LoanNotFound),
+        };
+        let count = Self::installment_count(&loan);
+        let rate_bps = (loan.interest_rate * 100.0) as i128;
+        let mut schedule = Vec::new(&env);
+        let mut previous_due = loan.originated_at;
+        let mut scheduled_before = 0i64;
+        for k in 1..=count {
+            let due = loan.originated_at + (k * INSTALLMENT_PERIOD_SECS).min(loan.term_secs);
+            let scheduled = (loan.amount as i128 * k as i128 / count as i128) as i64;
+            let principal = scheduled - scheduled_before;
+            let outstanding = (loan.amount - scheduled_before) as i128;
+            let interest = (outstanding * rate_bps * (due - previous_due) as i128
+                / (BPS_DENOMINATOR as i128 * SECONDS_PER_YEAR as i128)) as i64;
+            schedule.push_back((due, principal + interest, principal, interest));
+            previous_due = due;
+            scheduled_before = scheduled;
+        }
+        Ok(schedule)
+    }
+
+    /// Loans are repaid in equal principal installments, one every
+    /// `INSTALLMENT_PERIOD_SECS`, with the last one due at the end of the term.
+    fn installment_count(loan: &Loan) -> u64 {
+        ((loan.term_secs + INSTALLMENT_PERIOD_SECS - 1) / INSTALLMENT_PERIOD_SECS).max(1)
+    }
+
+    /// Due date of the earliest installment not yet covered by the principal
+    /// repaid so far, or None once the principal is fully repaid.
+    fn next_due_ts(loan: &Loan) -> Option<u64> {
+        let count = Self::installment_count(loan);
+        let principal_repaid = loan.repaid_amount - loan.interest_paid;
+        for k in 1..=count {
+            let scheduled = (loan.amount as i128 * k as i128 / count as i128) as i64;
+            if principal_repaid < scheduled {
+                return Some(loan.originated_at + (k * INSTALLMENT_PERIOD_SECS).min(loan.term_secs));
+            }
+        }
+        None
+    }
+
+    /// How many of the loan's installments have passed their due date plus
+    /// grace by `now`.
+    fn installments_past_grace(&self, loan: &Loan, now: u64) -> u64 {
+        (1..=Self::installment_count(loan))
+            .filter(|k| loan.originated_at + (k * INSTALLMENT_PERIOD_SECS).min(loan.term_secs) + self.pool.grace_period_secs < now)
+            .count() as u64
+    }
+
+    /// When the loan's penalty rate kicks in: the next due date plus grace.
+    fn delinquent_since(&self, loan: &Loan) -> Option<u64> {
+        Self::next_due_ts(loan).map(|due| due + self.pool.grace_period_secs)
+    }
+
+    /// Interest on the outstanding principal since the loan's last accrual,
+    /// at the loan's annual `interest_rate` (in percent). Time spent past the
+    /// grace period of a missed installment accrues at the penalty rate.
+    /// With a compounding period set, interest is worked out one period at a
+    /// time and each period's interest joins the balance for the next, along
+    /// with whatever was accrued in earlier periods and is still unpaid.
+    /// Interest booked partway through a period only joins at its end.
+    fn pending_interest(&self, loan: &Loan, now: u64) -> i64 {
+        self.interest_accrual(loan, now).0
+    }
+
+    /// `pending_interest` along with the loan's new `accrual_carry` and
+    /// `period_interest`. Each step is rounded together with the remainder
+    /// left over by the previous one, so accruing in many small steps books
+    /// the same total as one accrual over the whole span.
+    fn interest_accrual(&self, loan: &Loan, now: u64) -> (i64, i128, i64) {
+        if loan.status != LoanStatus::Active || !loan.accruing {
+            return (0, loan.accrual_carry, loan.period_interest);
+        }
+        let last = loan.last_accrual_ts;
+        let principal = Self::principal_outstanding(loan) as i128;
+        let denominator = BPS_DENOMINATOR as i128 * SECONDS_PER_YEAR as i128;
+        let mut carry = loan.accrual_carry;
+        let period = self.pool.compounding_period_secs;
+        if period == 0 {
+            let numerator = principal * self.rate_secs(loan, last, now) + carry;
+            let interest = self.round_div(numerator, denominator);
+            return (interest as i64, numerator - interest * denominator, 0);
+        }
+        // Interest booked earlier in the current period, as far as it's
+        // still unpaid, stays out of the balance until the period ends.
+        let unpaid = (loan.accrued_interest - loan.interest_paid).max(0) as i128;
+        let mut in_period = (loan.period_interest as i128).min(unpaid);
+        let mut balance = principal + unpaid - in_period;
+        let mut interest = 0i128;
+        let mut from = last;
+        while from < now {
+            let periods_done = from.saturating_sub(loan.originated_at) / period;
+            let period_end = loan.originated_at + (periods_done + 1) * period;
+            let to = period_end.min(now);
+            let numerator = balance * self.rate_secs(loan, from, to) + carry;
+            let step = self.round_div(numerator, denominator);
+            carry = numerator - step * denominator;
+            interest += step;
+            in_period += step;
+            if to == period_end {
+                balance += in_period;
+                in_period = 0;
+            }
+            from = to;
+        }
+        (interest as i64, carry, in_period as i64)
+    }
+
+    /// The loan's rate in bps multiplied by the seconds from `from` to `to`,
+    /// with time past the grace period of a missed installment at the
+    /// penalty rate and time in the loan's promo window free.
+    fn rate_secs(&self, loan: &Loan, from: u64, to: u64) -> i128 {
+        let from = from.max(loan.promo_ends_at).min(to);
+        let (normal_secs, penalty_secs) = match self.delinquent_since(loan) {
+            Some(since) if to > since => {
+                let start = since.max(from);
+                (start.saturating_sub(from), to - start)
+            }
+            _ => (to.saturating_sub(from), 0),
+        };
+        let rate_bps = (loan.interest_rate * 100.0) as i128;
+        let penalty_rate_bps = rate_bps * self.pool.penalty_multiplier_bps as i128 / BPS_DENOMINATOR as i128;
+        rate_bps * normal_secs as i128 + penalty_rate_bps * penalty_secs as i128
+    }
+
+    /// `numerator / denominator` under the pool's `rounding_mode`. Negative
+    /// numerators, as an accrual carry can leave, round the same way.
+    fn round_div(&self, numerator: i128, denominator: i128) -> i128 {
+        match self.pool.rounding_mode {
+            RoundingMode::Down => numerator.div_euclid(denominator),
+            RoundingMode::Up => -(-numerator).div_euclid(denominator),
+            RoundingMode::Nearest => (numerator + denominator / 2).div_euclid(denominator),
+        }
+    }
+
+    /// Accrues interest up to `now`, then applies `amount` to outstanding
+    /// interest and principal in the pool's `payment_application` order;
+    /// anything beyond both goes to principal. `savings_bps` of the payment, or
+    /// `tapered_savings_bps` once the loan's savings have reached the reward
+    /// threshold, is set aside; `insurance_funding_bps` of that goes to
+    /// insurance and the rest to the borrower's savings. Both are worked out
+    /// from this payment alone. Near `max_savings_per_loan` the set-aside
+    /// shrinks so the borrower's savings stop at the cap.
+    fn split_repayment(&self, loan: &Loan, amount: i64, now: u64) -> RepaymentSplit {
+        let accrued = self.pending_interest(loan, now);
+        let interest_due = loan.accrued_interest + accrued - loan.interest_paid;
+        let to_interest = match self.pool.payment_application {
+            PaymentApplication::InterestFirst => amount.min(interest_due),
+            PaymentApplication::PrincipalFirst => (amount - Self::principal_outstanding(loan)).max(0).min(interest_due),
+        };
+        let savings_bps = if loan.savings >= self.pool.reward_threshold {
+            self.pool.tapered_savings_bps
+        } else {
+            self.pool.savings_bps
+        };
+        let set_aside = self.round_div(amount as i128 * savings_bps as i128, BPS_DENOMINATOR as i128) as i64;
+        let mut to_insurance = set_aside * self.pool.insurance_funding_bps as i64 / BPS_DENOMINATOR;
+        let mut to_savings = set_aside - to_insurance;
+        let room = (self.pool.max_savings_per_loan - loan.savings).max(0);
+        if to_savings > room {
+            to_insurance = (to_insurance as i128 * room as i128 / to_savings as i128) as i64;
+            to_savings = room;
+        }
+        RepaymentSplit {
+            to_principal: amount - to_interest,
+            to_interest,
+            to_savings,
+            to_insurance,
+            interest_to_insurance: to_interest * self.pool.interest_insurance_bps as i64 / BPS_DENOMINATOR,
+            interest_to_yield: to_interest * self.pool.interest_yield_bps as i64 / BPS_DENOMINATOR,
+        }
+    }
+
+    /// The rate a loan priced at `base` earns with `savings` put away: half a
+    /// point off for every full `reward_threshold` saved. It depends only on
+    /// the savings total, not on how many payments it took to get there.
+    fn rate_for_savings(&self, base: f32, savings: i64) -> f32 {
+        if self.pool.reward_threshold <= 0 {
+            return base;
+        }
+        let steps = savings / self.pool.reward_threshold;
+        (base - 0.5 * steps as f32).max(0.0)
+    }
+
+    /// The rate `loan` should carry once priced at `base`: the savings reward
+    /// taken off, down to the loan's floor. Overdue loans keep saving but
+    /// only earn the reward once current, so while an active loan is past
+    /// the grace period of a missed installment it keeps no more off `base`
+    /// than it already had.
+    fn rate_with_reward(&self, loan: &Loan, base: f32, now: u64) -> f32 {
+        let rewarded = self.rate_for_savings(base, loan.savings).max(loan.min_rate_bps as f32 / 100.0);
+        let overdue = loan.status == LoanStatus::Active && self.delinquent_since(loan).map_or(false, |since| now > since);
+        if overdue {
+            rewarded.max(loan.interest_rate + (base - loan.base_rate))
+        } else {
+            rewarded
+        }
+    }
+
+    /// The pool rate adjusted for the borrower's credit band.
+    fn offered_rate(&self, env: &Env, borrower: &Address) -> f32 {
+        let score = self.blended_credit_score(env, borrower);
+        let band = CREDIT_BANDS.iter().rposition(|floor| score >= *floor).unwrap_or(0);
+        (self.calculate_interest_rate() + CREDIT_BAND_SPREADS[band]).max(0.0)
+    }
+
+    /// The internal score averaged with the bureau's, when a bureau is set
+    /// and knows the borrower.
+    fn blended_credit_score(&self, env: &Env, borrower: &Address) -> u32 {
+        let internal = self.get_credit_score(borrower.clone());
+        let external = match &self.pool.credit_bureau {
+            Some(bureau) => CreditBureauClient::new(env, bureau).score(borrower),
+            None => None,
+        };
+        match external {
+            Some(score) => (internal + score.min(MAX_CREDIT_SCORE)) / 2,
+            None => internal,
+        }
+    }
+
+    fn report_to_bureau(&self, env: &Env, borrower: &Address, loan_id: u64, defaulted: bool) {
+        if let Some(bureau) = &self.pool.credit_bureau {
+            CreditBureauClient::new(env, bureau).report(borrower, &loan_id, &defaulted);
+        }
+    }
+
+    fn calculate_interest_rate(&self) -> f32 {
+        let utilization = self.get_utilization_bps() as f32 / BPS_DENOMINATOR as f32;
+        self.pool.base_interest_rate + (utilization * 2.0).min(5.0)
+    }
+
+    /// Active principal over idle funds, in bps, as used for pricing. Capped
+    /// at `UTILIZATION_CAP_BPS`, where the rate premium stops growing, and
+    /// pinned there once nothing is left idle.
+    pub fn get_utilization_bps(&self) -> u32 {
+        let lent = self.loans().iter().filter(|l| l.status == LoanStatus::Active).map(|l| l.amount).sum::<i64>();
+        if lent <= 0 {
+            return 0;
+        }
+        if self.pool.total_funds <= 0 {
+            return UTILIZATION_CAP_BPS;
+        }
+        (lent as i128 * BPS_DENOMINATOR as i128 / self.pool.total_funds as i128).min(UTILIZATION_CAP_BPS as i128) as u32
+    }
+
+    fn check_blend_pool_availability(&self, env: &Env, amount: i64) -> bool {
+        match &self.pool.blend_pool {
+            Some(blend) => BlendClient::new(env, blend).available() >= amount,
+            None => false,
+        }
+    }
+
+    /// Checks a loan of `amount` can be funded, drawing on Blend for
+    /// whatever the pool's idle funds above the reserve don't cover.
+    fn check_funding(&self, env: &Env, amount: i64) -> Result<(), LoanError> {
+        let from_blend = self.blend_draw_for(amount);
+        if from_blend == 0 {
+            return Ok(());
+        }
+        if self.pool.blend_pool.is_none() {
+            if self.pool.total_funds < amount {
+                return Err(LoanError::InsufficientFunds);
+            }
+            return Err(LoanError::ReserveBreached);
+        }
+        if !self.check_blend_pool_availability(env, from_blend) {
+            return Err(LoanError::BlendPoolUnavailable);
+        }
+        Ok(())
+    }
+
+    /// Part of a loan of `amount` the pool can't fund itself without dipping
+    /// below its reserve.
+    fn blend_draw_for(&self, amount: i64) -> i64 {
+        (amount - (self.pool.total_funds - self.required_reserve()).max(0)).max(0)
+    }
+
+    /// All loans in ascending id order. `Map` keeps its keys sorted, so the
+    /// order never depends on storage history; aggregate queries iterate
+    /// this rather than reaching into storage themselves.
+    fn loans(&self) -> Vec<Loan> {
+        self.pool.loans.values()
+    }
+
+    /// Up to `limit` loans starting at position `start` in the book, in id order.
+    pub fn list_loans(&self, env: Env, start: u32, limit: u32) -> Vec<Loan> {
+        let loans = self.loans();
+        let end = start.saturating_add(limit).min(loans.len());
+        let mut page = Vec::new(&env);
+        for index in start..end {
+            page.push_back(loans.get(index).unwrap());
+        }
+        page
+    }
+
+    pub fn get_loan_by_id(&self, loan_id: u64) -> Option<Loan> {
+        self.pool.loans.get(loan_id)
+    }
+
+    pub fn get_loan_status(&self, borrower: Address) -> Option<Loan> {
+        self.loans().iter().find(|l| l.borrower == borrower && l.status == LoanStatus::Active).cloned()
+    }
+
+    /// Total booked balance across the borrower's active loans.
+    pub fn borrower_outstanding(&self, borrower: Address) -> i64 {
+        self.loans().iter()
+            .filter(|l| l.borrower == borrower && l.status == LoanStatus::Active)
+            .map(|l| (l.amount + l.accrued_interest - l.repaid_amount).max(0))
+            .sum::<i64>()
+    }
+
+    /// Total owed on active loans and its share of pool assets (owed plus
+    /// idle funds), as `(total_outstanding, utilization_bps)`. With `live`
+    /// set, interest not yet booked is accrued up to now first, as a
+    /// read-only projection; otherwise only booked figures are used.
+    pub fn outstanding_stats(&self, env: Env, live: bool) -> (i64, u32) {
+        let now = env.ledger().timestamp();
+        let outstanding = self.loans().iter()
+            .filter(|l| l.status == LoanStatus::Active)
+            .map(|l| {
+                let pending = if live { self.pending_interest(&l, now) } else { 0 };
+                (l.amount + l.accrued_interest + pending - l.repaid_amount).max(0)
+            })
+            .sum::<i64>();
+        let assets = outstanding + self.pool.total_funds;
+        let utilization = if assets > 0 {
+            (outstanding as i128 * BPS_DENOMINATOR as i128 / assets as i128) as u32
+        } else {
+            0
+        };
+        (outstanding, utilization)
+    }
+
+    /// Idle funds plus the insurance fund over what's owed on open loans plus
+    /// borrower savings held, in bps. Returns `u32::MAX` when there's
+    /// nothing on the liability side.
+    pub fn solvency_ratio(&self, env: Env) -> u32 {
+        let now = env.ledger().timestamp();
+        let outstanding = self.loans().iter()
+            .filter(|l| l.status == LoanStatus::Active || l.status == LoanStatus::Defaulted)
+            .map(|l| self.remaining_balance(&l, now))
+            .sum::<i64>();
+        let savings = self.loans().iter().map(|l| l.savings).sum::<i64>();
+        let liabilities = outstanding + savings;
+        if liabilities <= 0 {
+            return u32::MAX;
+        }
+        let assets = (self.pool.total_funds + self.pool.insurance_fund).max(0) as i128;
+        (assets * BPS_DENOMINATOR as i128 / liabilities as i128).min(u32::MAX as i128) as u32
+    }
+
+    /// Models a shock without changing anything: rates on active loans jump
+    /// by `rate_shock_bps` for the rest of their terms and `default_rate_bps`
+    /// of what collateral doesn't cover is lost. Returns the insurance
+    /// drawdown that loss implies, capped at the fund, and how many loans
+    /// would owe more by maturity than their collateral supports at
+    /// `max_ltv_bps`, as `(drawdown, breaches)`.
+    pub fn simulate_shock(&self, env: Env, default_rate_bps: u32, rate_shock_bps: u32) -> (i64, u32) {
+        let now = env.ledger().timestamp();
+        let mut uncovered = 0i128;
+        let mut breaches = 0u32;
+        for loan in self.loans().iter().filter(|l| l.status == LoanStatus::Active) {
+            let maturity = loan.originated_at + loan.term_secs;
+            let rate_bps = (loan.interest_rate * 100.0) as i128 + rate_shock_bps as i128;
+            let future_interest = Self::principal_outstanding(&loan) as i128 * rate_bps * maturity.saturating_sub(now) as i128
+                / (BPS_DENOMINATOR as i128 * SECONDS_PER_YEAR as i128);
+            let owed = self.remaining_balance(&loan, now) as i128 + future_interest;
+            let supported = loan.collateral as i128 * self.pool.max_ltv_bps as i128 / BPS_DENOMINATOR as i128;
+            if owed > supported {
+                breaches += 1;
+            }
+            uncovered += (owed - loan.collateral as i128).max(0);
+        }
+        let loss = uncovered * default_rate_bps.min(BPS_DENOMINATOR as u32) as i128 / BPS_DENOMINATOR as i128;
+        (loss.min(self.pool.insurance_fund.max(0) as i128) as i64, breaches)
+    }
+
+    /// Checks the pool's bookkeeping invariants and reports each as
+    /// `(name, holds)`: no negative balances, idle funds plus the pool's
+    /// share of outstanding principal and the savings and insurance carved
+    /// out of repayments covering all shares, share totals matching the lender
+    /// positions, and no loan with more principal repaid than lent.
+    pub fn health_check(&self, env: Env) -> Vec<(Symbol, bool)> {
+        let mut checks = Vec::new(&env);
+        checks.push_back((symbol_short!("funds"), self.pool.total_funds >= 0));
+        checks.push_back((symbol_short!("insurance"), self.pool.insurance_fund >= 0));
+
+        let lent = self.loans().iter()
+            .filter(|l| l.status == LoanStatus::Active || l.status == LoanStatus::Defaulted)
+            .map(|l| Self::principal_outstanding(&l) - l.blend_funded)
+            .sum::<i64>();
+        let savings = self.loans().iter().map(|l| l.savings).sum::<i64>();
+        let carved = savings + self.pool.insurance_from_savings;
+        checks.push_back((symbol_short!("backing"), self.pool.total_funds + lent + carved >= self.pool.total_shares));
+
+        let mut shares = 0i64;
+        let mut listed_ok = true;
+        for (_, position) in self.pool.lenders.iter() {
+            shares += position.shares;
+            listed_ok &= position.listed >= 0 && position.listed <= position.shares;
+        }
+        checks.push_back((symbol_short!("shares"), shares == self.pool.total_shares && listed_ok));
+
+        let overpaid = self.loans().iter()
+            .any(|l| l.status == LoanStatus::Active && l.repaid_amount - l.interest_paid > l.amount);
+        checks.push_back((symbol_short!("loans"), !overpaid));
+        checks
+    }
+
+    pub fn get_pool_stats(&self) -> (i64, u32, i64) {
+        let active_loans = self.loans().iter().filter(|l| l.status == LoanStatus::Active).count() as u32;
+        let total_savings = self.loans().iter().map(|l| l.savings).sum::<i64>();
+        (self.pool.total_funds, active_loans, total_savings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use soroban_sdk::testutils::{Address as _, Ledger};
+
+    const TERM: u64 = 365 * 24 * 60 * 60;
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn apply_change(env: &Env, contract: &mut MicroLoanContract, owner: &Address, change: ParamChange) {
+        let id = contract.queue_param_change(env, owner.clone(), change).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += PARAM_TIMELOCK_SECS);
+        contract.execute_param_change(env, owner.clone(), id).unwrap();
+    }
+
+    fn mint(env: &Env, contract: &MicroLoanContract, to: &Address, amount: i64) {
+        token::StellarAssetClient::new(env, &contract.pool.token).mint(to, &amount);
+    }
+
+    #[contract]
+    pub struct MockBlend;
+
+    #[contractimpl]
+    impl MockBlend {
+        pub fn init(env: Env, token: Address) {
+            env.storage().instance().set(&symbol_short!("token"), &token);
+        }
+
+        pub fn available(env: Env) -> i64 {
+            let token: Address = env.storage().instance().get(&symbol_short!("token")).unwrap();
+            token::Client::new(&env, &token).balance(&env.current_contract_address()) as i64
+        }
+
+        pub fn draw(env: Env, to: Address, amount: i64) {
+            let token: Address = env.storage().instance().get(&symbol_short!("token")).unwrap();
+            token::Client::new(&env, &token).transfer(&env.current_contract_address(), &to, &amount);
+        }
+
+        pub fn repay(env: Env, from: Address, amount: i64) {
+            let repaid: i64 = env.storage().instance().get(&symbol_short!("repaid")).unwrap_or(0);
+            env.storage().instance().set(&symbol_short!("repaid"), &(repaid + amount));
+        }
+
+        pub fn repaid(env: Env) -> i64 {
+            env.storage().instance().get(&symbol_short!("repaid")).unwrap_or(0)
+        }
+    }
+
+    #[contract]
+    pub struct MockBureau;
+
+    #[contractimpl]
+    impl MockBureau {
+        pub fn set_score(env: Env, borrower: Address, score: u32) {
+            env.storage().instance().set(&borrower, &score);
+        }
+
+        pub fn score(env: Env, borrower: Address) -> Option<u32> {
+            env.storage().instance().get(&borrower)
+        }
+
+        pub fn report(env: Env, borrower: Address, loan_id: u64, defaulted: bool) {
+            env.storage().instance().set(&symbol_short!("last"), &(borrower, loan_id, defaulted));
+        }
+
+        pub fn last_report(env: Env) -> Option<(Address, u64, bool)> {
+            env.storage().instance().get(&symbol_short!("last"))
+        }
+    }
+
+    fn setup(env: &Env) -> (MicroLoanContract, Address) {
+        env.mock_all_auths();
+        let owner = Address::random(env);
+        let token = env.register_stellar_asset_contract(owner.clone());
+        token::StellarAssetClient::new(env, &token).mint(&owner, &10_000_000_000);
+        let contract = MicroLoanContract::initialize(env, owner.clone(), token, Address::random(env), 10_000_000_000, 5.0).unwrap();
+        (contract, owner)
+    }
+
+    #[test]
+    fn test_loan_lifecycle() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let owner = Address::random(&env);
+        let token = env.register_stellar_asset_contract(owner.clone());
+        token::StellarAssetClient::new(&env, &token).mint(&owner, &10_000_000_000);
+        let contract = MicroLoanContract::initialize(&env, owner.clone(), token, Address::random(&env), 10_000_000_000, 5.0).unwrap();
+        let borrower = Address::random(&env);
+        let mut contract = contract;
+        mint(&env, &contract, &borrower, 1_000_000_000);
+
+        assert!(contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+        let loan = contract.get_loan_status(borrower.clone()).unwrap();
+        assert_eq!(loan.amount, 500_000_000);
+        assert_eq!(loan.status, LoanStatus::Active);
+
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        let loan = contract.get_loan_status(borrower.clone()).unwrap();
+        assert_eq!(loan.repaid_amount, 100_000_000);
+        assert_eq!(loan.savings, 2_500_000);
+    }
+
+    #[test]
+    fn test_insurance_funding_ratio() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 900_000_000, TERM, 0, None).is_ok());
+
+        let before = contract.pool.insurance_fund;
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        let default_growth = contract.pool.insurance_fund - before;
+
+        assert!(contract.set_insurance_funding_bps(&env, owner.clone(), 10_000).is_ok());
+        let before = contract.pool.insurance_fund;
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        assert!(contract.pool.insurance_fund - before > default_growth);
+
+        assert!(contract.set_insurance_funding_bps(&env, owner.clone(), 1_000).is_ok());
+        let before = contract.pool.insurance_fund;
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        assert!(contract.pool.insurance_fund - before < default_growth);
+
+        let stranger = Address::random(&env);
+        assert!(contract.set_insurance_funding_bps(&env, stranger, 2_000).is_err());
+    }
+
+    #[test]
+    fn test_preview_repayment_matches_repay() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += 30 * 24 * 60 * 60);
+
+        let (to_principal, to_interest, to_savings, to_insurance, penalty) =
+            contract.preview_repayment(&env, loan_id, 100_000_000).unwrap();
+        assert!(to_interest > 0);
+        assert_eq!(penalty, 0);
+        assert_eq!(to_principal + to_interest, 100_000_000);
+
+        let before = contract.get_loan_status(borrower.clone()).unwrap();
+        let insurance_before = contract.pool.insurance_fund;
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        let after = contract.get_loan_status(borrower.clone()).unwrap();
+
+        assert_eq!(after.interest_paid - before.interest_paid, to_interest);
+        assert_eq!(
+            (after.repaid_amount - after.interest_paid) - (before.repaid_amount - before.interest_paid),
+            to_principal
+        );
+        assert_eq!(after.savings - before.savings, to_savings);
+        assert_eq!(contract.pool.insurance_fund - insurance_before, to_insurance);
+
+        // Closing early: the preview leaves out the interest the rebate waives.
+        assert!(contract.set_early_repayment_terms(&env, owner.clone(), 5_000, 0).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += 10 * DAY);
+        let payoff = contract.estimate_payoff_at(&env, loan_id, env.ledger().timestamp()).unwrap();
+        let (to_principal, to_interest, _, _, penalty) = contract.preview_repayment(&env, loan_id, payoff).unwrap();
+        assert_eq!(penalty, 0);
+        let before = contract.get_loan_status(borrower.clone()).unwrap();
+        assert_eq!(to_principal, MicroLoanContract::principal_outstanding(&before));
+        assert!(contract.repay_loan(&env, borrower.clone(), payoff, None).is_ok());
+        let after = contract.get_loan_by_id(loan_id).unwrap();
+        assert_eq!(after.status, LoanStatus::Repaid);
+        assert_eq!(after.interest_paid - before.interest_paid, to_interest);
+
+        // Prepaying with a penalty set: the preview shows what's charged on top.
+        assert!(contract.set_early_repayment_terms(&env, owner.clone(), 0, 200).is_ok());
+        let prepayer = Address::random(&env);
+        mint(&env, &contract, &prepayer, 1_000_000_000);
+        let prepaid_id = contract.request_loan(&env, prepayer.clone(), 100_000_000, TERM, 0, None).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += DAY);
+        let (to_principal, _, _, _, penalty) = contract.preview_repayment(&env, prepaid_id, 50_000_000).unwrap();
+        assert!(penalty > 0);
+        assert_eq!(penalty, to_principal * 200 / 10_000);
+        let token = token::Client::new(&env, &contract.pool.token);
+        let balance_before = token.balance(&prepayer);
+        assert!(contract.repay_loan(&env, prepayer.clone(), 50_000_000, None).is_ok());
+        assert_eq!(balance_before - token.balance(&prepayer), (50_000_000 + penalty) as i128);
+    }
+
+    #[test]
+    fn test_loan_statement_mid_loan() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).unwrap();
+        let rate = contract.get_loan_status(borrower.clone()).unwrap().interest_rate;
+
+        env.ledger().with_mut(|li| li.timestamp += 30 * 24 * 60 * 60);
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += 30 * 24 * 60 * 60);
+
+        let rate_bps = (rate * 100.0) as i128;
+        let first = (500_000_000i128 * rate_bps * 2_592_000 / (10_000 * 31_536_000)) as i64;
+        let principal_left = 500_000_000 - (100_000_000 - first);
+        let second = (principal_left as i128 * rate_bps * 2_592_000 / (10_000 * 31_536_000)) as i64;
+
+        let statement = contract.loan_statement(&env, loan_id).unwrap();
+        assert_eq!(statement.originated_at, 1_000);
+        assert_eq!(statement.amount, 500_000_000);
+        assert_eq!(statement.interest_rate, rate);
+        assert_eq!(statement.interest_accrued, first + second);
+        assert_eq!(statement.total_repaid, 100_000_000);
+        assert_eq!(statement.savings, 2_500_000);
+        assert_eq!(statement.remaining_balance, 500_000_000 + first + second - 100_000_000);
+        assert_eq!(statement.status, LoanStatus::Active);
+    }
+
+    #[test]
+    fn test_savings_taper_past_threshold() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_savings_taper(&env, owner.clone(), 500, 200, 5_000_000).is_ok());
+        let veteran = Address::random(&env);
+        mint(&env, &contract, &veteran, 10_000_000_000);
+        let newcomer = Address::random(&env);
+        mint(&env, &contract, &newcomer, 10_000_000_000);
+        assert!(contract.request_loan(&env, veteran.clone(), 1_000_000_000, TERM, 0, None).is_ok());
+        assert!(contract.request_loan(&env, newcomer.clone(), 1_000_000_000, TERM, 0, None).is_ok());
+        assert!(contract.repay_loan(&env, veteran.clone(), 300_000_000, None).is_ok());
+
+        let veteran_before = contract.get_loan_status(veteran.clone()).unwrap().savings;
+        assert!(veteran_before >= 5_000_000);
+        assert!(contract.repay_loan(&env, veteran.clone(), 100_000_000, None).is_ok());
+        assert!(contract.repay_loan(&env, newcomer.clone(), 100_000_000, None).is_ok());
+
+        let veteran_saved = contract.get_loan_status(veteran.clone()).unwrap().savings - veteran_before;
+        let newcomer_saved = contract.get_loan_status(newcomer.clone()).unwrap().savings;
+        assert_eq!(veteran_saved, 1_000_000);
+        assert_eq!(newcomer_saved, 2_500_000);
+    }
+
+    #[test]
+    fn test_recover_token() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (contract, owner) = setup(&env);
+        let foreign = env.register_stellar_asset_contract(owner.clone());
+        token::StellarAssetClient::new(&env, &foreign).mint(&env.current_contract_address(), &50_000_000);
+        let to = Address::random(&env);
+
+        assert!(contract.recover_token(&env, owner.clone(), foreign.clone(), to.clone(), 50_000_000).is_ok());
+        assert_eq!(token::Client::new(&env, &foreign).balance(&to), 50_000_000);
+
+        let lending = contract.pool.token.clone();
+        assert_eq!(
+            contract.recover_token(&env, owner.clone(), lending, to.clone(), 1),
+            Err(LoanError::ProtectedToken)
+        );
+    }
+
+    #[test]
+    fn test_min_repayment_allows_final_payoff() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_min_repayment(&env, owner.clone(), 5_000_000).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 10_000_000, TERM, 0, None).is_ok());
+        assert!(contract.repay_loan(&env, borrower.clone(), 8_000_000, None).is_ok());
+
+        assert_eq!(contract.repay_loan(&env, borrower.clone(), 1_000_000, None), Err(LoanError::RepaymentBelowMinimum));
+        assert!(contract.repay_loan(&env, borrower.clone(), 2_000_000, None).is_ok());
+        assert!(contract.get_loan_status(borrower.clone()).is_none());
+    }
+
+    #[test]
+    fn test_origination_cooldown() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_cooldown_secs(&env, owner.clone(), 3_600).is_ok());
+        let borrower = Address::random(&env);
+        env.ledger().with_mut(|li| li.timestamp = 10_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).is_ok());
+
+        env.ledger().with_mut(|li| li.timestamp += 3_599);
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None), Err(LoanError::BorrowerInCooldown));
+
+        env.ledger().with_mut(|li| li.timestamp += 1);
+        assert!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).is_ok());
+    }
+
+    #[test]
+    fn test_max_utilization_cap() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_max_utilization_bps(&env, owner.clone(), 5_000).is_ok());
+        for _ in 0..5 {
+            let borrower = Address::random(&env);
+            assert!(contract.request_loan(&env, borrower, 1_000_000_000, TERM, 0, None).is_ok());
+        }
+
+        let borrower = Address::random(&env);
+        assert!(contract.pool.total_funds >= 1_000_000_000);
+        assert_eq!(
+            contract.request_loan(&env, borrower, 1_000_000_000, TERM, 0, None),
+            Err(LoanError::UtilizationCapExceeded)
+        );
+    }
+
+    #[test]
+    fn test_notification_prefs_round_trip() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let borrower = Address::random(&env);
+        assert_eq!(contract.get_notification_prefs(borrower.clone()), 0);
+
+        let flags = NOTIFY_DUE_SOON | NOTIFY_REWARD;
+        assert!(contract.set_notification_prefs(&env, borrower.clone(), flags).is_ok());
+        assert_eq!(contract.get_notification_prefs(borrower.clone()), flags);
+        assert!(contract.set_notification_prefs(&env, borrower.clone(), 1 << 7).is_err());
+    }
+
+    #[test]
+    fn test_partial_repayment_releases_excess_collateral() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (mut contract, owner) = setup(&env);
+        let collateral_token = env.register_stellar_asset_contract(owner.clone());
+        apply_change(&env, &mut contract, &owner, ParamChange::CollateralToken(collateral_token.clone()));
+        assert!(contract.set_max_ltv_bps(&env, owner.clone(), 8_000).is_ok());
+
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        token::StellarAssetClient::new(&env, &collateral_token).mint(&borrower, &1_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 400_000_000, TERM, 0, None).unwrap();
+        assert!(contract.add_collateral(&env, borrower.clone(), loan_id, 500_000_000).is_ok());
+
+        assert!(contract.repay_loan(&env, borrower.clone(), 200_000_000, None).is_ok());
+        let loan = contract.get_loan_status(borrower.clone()).unwrap();
+        let released = token::Client::new(&env, &collateral_token).balance(&borrower) - 500_000_000;
+        assert_eq!(released, 250_000_000);
+        assert_eq!(loan.collateral, 250_000_000);
+        assert!(loan.collateral * 8_000 / 10_000 >= 200_000_000);
+    }
+
+    #[test]
+    fn test_pool_apy_after_interest_income() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        assert_eq!(contract.pool_apy_bps(&env), 0);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 1_000_000_000, TERM, 0, None).is_ok());
+
+        env.ledger().with_mut(|li| li.timestamp += 30 * 24 * 60 * 60);
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+
+        // ~4.1M interest over 30 days on ~9.1B of funds is ~55 bps a year.
+        let apy = contract.pool_apy_bps(&env);
+        assert!(apy >= 50 && apy <= 60);
+    }
+
+    #[test]
+    fn test_transfer_shares_moves_future_yield() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (mut contract, owner) = setup(&env);
+        let lending = contract.pool.token.clone();
+        let alice = Address::random(&env);
+        let bob = Address::random(&env);
+        token::StellarAssetClient::new(&env, &lending).mint(&alice, &10_000_000_000);
+        assert!(contract.deposit(&env, alice.clone(), 10_000_000_000).is_ok());
+
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 1_000_000_000, TERM, 0, None).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += 30 * 24 * 60 * 60);
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        let alice_earned = contract.claimable_yield(alice.clone());
+        assert!(alice_earned > 0);
+
+        assert!(contract.transfer_shares(&env, alice.clone(), bob.clone(), 10_000_000_000).is_ok());
+        assert_eq!(contract.get_shares(alice.clone()), 0);
+        assert_eq!(contract.get_shares(bob.clone()), 10_000_000_000);
+        assert_eq!(contract.claimable_yield(alice.clone()), alice_earned);
+        assert_eq!(contract.claimable_yield(bob.clone()), 0);
+
+        env.ledger().with_mut(|li| li.timestamp += 30 * 24 * 60 * 60);
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        assert_eq!(contract.claimable_yield(alice.clone()), alice_earned);
+        assert!(contract.claimable_yield(bob.clone()) > 0);
+        assert!(contract.claimable_yield(owner.clone()) > 0);
+    }
+
+    #[test]
+    fn test_share_listing_fill_and_cancel() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let (mut contract, _owner) = setup(&env);
+        let lending = contract.pool.token.clone();
+        let seller = Address::random(&env);
+        let buyer = Address::random(&env);
+        token::StellarAssetClient::new(&env, &lending).mint(&seller, &1_000_000_000);
+        token::StellarAssetClient::new(&env, &lending).mint(&buyer, &500_000_000);
+        assert!(contract.deposit(&env, seller.clone(), 1_000_000_000).is_ok());
+
+        let listing = contract.list_shares(&env, seller.clone(), 400_000_000, 420_000_000).unwrap();
+        assert_eq!(contract.get_available_shares(seller.clone()), 600_000_000);
+        assert!(contract.transfer_shares(&env, seller.clone(), buyer.clone(), 700_000_000).is_err());
+
+        assert!(contract.buy_shares(&env, buyer.clone(), listing).is_ok());
+        assert_eq!(contract.get_shares(buyer.clone()), 400_000_000);
+        assert_eq!(contract.get_shares(seller.clone()), 600_000_000);
+        assert_eq!(token::Client::new(&env, &lending).balance(&seller), 420_000_000);
+        assert_eq!(token::Client::new(&env, &lending).balance(&buyer), 80_000_000);
+        assert!(contract.buy_shares(&env, buyer.clone(), listing).is_err());
+
+        let listing = contract.list_shares(&env, seller.clone(), 100_000_000, 100_000_000).unwrap();
+        assert_eq!(contract.get_available_shares(seller.clone()), 500_000_000);
+        assert!(contract.cancel_listing(&env, seller.clone(), listing).is_ok());
+        assert_eq!(contract.get_available_shares(seller.clone()), 600_000_000);
+        assert!(contract.buy_shares(&env, buyer.clone(), listing).is_err());
+    }
+
+    #[test]
+    fn test_penalty_rate_while_overdue() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 300_000_000, 90 * DAY, 0, None).unwrap();
+        let interest_at = |contract: &MicroLoanContract, ts: u64| {
+            env.ledger().with_mut(|li| li.timestamp = ts);
+            contract.loan_statement(&env, loan_id).unwrap().interest_accrued
+        };
+
+        // First installment is due on day 30, penalty applies from day 35.
+        let current = interest_at(&contract, 20 * DAY) - interest_at(&contract, 10 * DAY);
+        let overdue = interest_at(&contract, 50 * DAY) - interest_at(&contract, 40 * DAY);
+        assert!(overdue > current);
+        assert!((overdue - current * 3 / 2).abs() <= 2);
+
+        // Catching up the interest and first installment makes it current.
+        let owed_interest = interest_at(&contract, 50 * DAY);
+        assert!(contract.repay_loan(&env, borrower.clone(), owed_interest + 100_000_000, None).is_ok());
+        let cured = interest_at(&contract, 60 * DAY) - interest_at(&contract, 50 * DAY);
+        assert!((cured * 3 - current * 2).abs() <= 6);
+    }
+
+    #[test]
+    fn test_amortization_schedule_sums() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let borrower = Address::random(&env);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 300_000_000, 60 * DAY, 0, None).unwrap();
+        let rate_bps = (contract.get_loan_status(borrower.clone()).unwrap().interest_rate * 100.0) as i128;
+
+        let schedule = contract.amortization_schedule(&env, loan_id).unwrap();
+        assert_eq!(schedule.len(), 2);
+        let principal: i64 = schedule.iter().map(|(_, _, p, _)| p).sum();
+        let interest: i64 = schedule.iter().map(|(_, _, _, i)| i).sum();
+        assert_eq!(principal, 300_000_000);
+
+        let first = (300_000_000i128 * rate_bps * (30 * DAY) as i128 / (10_000 * 31_536_000)) as i64;
+        let second = (150_000_000i128 * rate_bps * (30 * DAY) as i128 / (10_000 * 31_536_000)) as i64;
+        assert_eq!(interest, first + second);
+        assert_eq!(schedule.get(1).unwrap().0, 60 * DAY);
+    }
+
+    #[test]
+    fn test_repayment_idempotency_key() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+        let key = BytesN::from_array(&env, &[1; 32]);
+
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, Some(key.clone())).is_ok());
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, Some(key.clone())).is_ok());
+        assert_eq!(contract.get_loan_status(borrower.clone()).unwrap().repaid_amount, 100_000_000);
+
+        let other = BytesN::from_array(&env, &[2; 32]);
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, Some(other)).is_ok());
+        assert_eq!(contract.get_loan_status(borrower.clone()).unwrap().repaid_amount, 200_000_000);
+
+        env.ledger().with_mut(|li| li.timestamp += DAY);
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, Some(key)).is_ok());
+        assert_eq!(contract.get_loan_status(borrower.clone()).unwrap().repaid_amount, 300_000_000);
+    }
+
+    #[test]
+    fn test_reserve_split_routes_interest() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 1_000_000_000, TERM, 0, None).unwrap();
+        assert!(contract.set_reserve_split(&env, owner.clone(), 2_000, 5_000).is_ok());
+        assert!(contract.set_reserve_split(&env, owner.clone(), 6_000, 5_000).is_err());
+
+        env.ledger().with_mut(|li| li.timestamp += 30 * DAY);
+        let (_, interest, savings, insurance, _) = contract.preview_repayment(&env, loan_id, 100_000_000).unwrap();
+        let funds_before = contract.pool.total_funds;
+        let insurance_before = contract.pool.insurance_fund;
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        let to_insurance = interest * 2_000 / 10_000;
+        let to_yield = interest * 5_000 / 10_000;
+        assert_eq!(insurance, savings + to_insurance);
+        assert_eq!(contract.pool.insurance_fund - insurance_before, insurance);
+        assert_eq!(contract.pool.total_funds - funds_before, 100_000_000 - savings - insurance - to_yield);
+        assert!((contract.claimable_yield(owner.clone()) - to_yield).abs() <= 1);
+
+        assert!(contract.set_reserve_split(&env, owner.clone(), 0, 0).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += 30 * DAY);
+        let (_, interest, savings, insurance, _) = contract.preview_repayment(&env, loan_id, 100_000_000).unwrap();
+        assert!(interest > 0);
+        let funds_before = contract.pool.total_funds;
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        assert_eq!(contract.pool.total_funds - funds_before, 100_000_000 - savings - insurance);
+    }
+
+    #[test]
+    fn test_can_borrow_matches_request_loan() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let borrower = Address::random(&env);
+        assert!(contract.set_cooldown_secs(&env, owner.clone(), 3_600).is_ok());
+        assert!(contract.set_max_utilization_bps(&env, owner.clone(), 1_500).is_ok());
+
+        assert_eq!(contract.can_borrow(&env, borrower.clone(), 5_000_000), Err(LoanError::AmountOutOfRange));
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 5_000_000, TERM, 0, None), Err(LoanError::AmountOutOfRange));
+
+        assert_eq!(contract.can_borrow(&env, borrower.clone(), 1_000_000_000), Ok(()));
+        assert!(contract.request_loan(&env, borrower.clone(), 1_000_000_000, TERM, 0, None).is_ok());
+
+        assert_eq!(contract.can_borrow(&env, borrower.clone(), 100_000_000), Err(LoanError::BorrowerInCooldown));
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None), Err(LoanError::BorrowerInCooldown));
+
+        let other = Address::random(&env);
+        assert_eq!(contract.can_borrow(&env, other.clone(), 1_000_000_000), Err(LoanError::UtilizationCapExceeded));
+        assert_eq!(contract.request_loan(&env, other.clone(), 1_000_000_000, TERM, 0, None), Err(LoanError::UtilizationCapExceeded));
+    }
+
+    #[test]
+    fn test_portfolio_by_band() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let low = Address::random(&env);
+        let mid = Address::random(&env);
+        let high = Address::random(&env);
+        let high_too = Address::random(&env);
+        assert!(contract.set_credit_score(&env, owner.clone(), low.clone(), 420).is_ok());
+        assert!(contract.set_credit_score(&env, owner.clone(), high.clone(), 760).is_ok());
+        assert!(contract.set_credit_score(&env, owner.clone(), high_too.clone(), 700).is_ok());
+
+        assert!(contract.request_loan(&env, low.clone(), 100_000_000, TERM, 0, None).is_ok());
+        assert!(contract.request_loan(&env, mid.clone(), 200_000_000, TERM, 0, None).is_ok());
+        assert!(contract.request_loan(&env, high.clone(), 300_000_000, TERM, 0, None).is_ok());
+        assert!(contract.request_loan(&env, high_too.clone(), 400_000_000, TERM, 0, None).is_ok());
+
+        let bands = contract.portfolio_by_band(&env);
+        assert_eq!(bands.len(), 3);
+        assert_eq!(bands.get(0).unwrap(), (0, 1, 100_000_000));
+        assert_eq!(bands.get(1).unwrap(), (500, 1, 200_000_000));
+        assert_eq!(bands.get(2).unwrap(), (700, 2, 700_000_000));
+    }
+
+    #[test]
+    fn test_term_bounds() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_term_bounds(&env, owner.clone(), 60 * DAY, 180 * DAY).is_ok());
+        let borrower = Address::random(&env);
+
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 100_000_000, 30 * DAY, 0, None), Err(LoanError::InvalidTerm));
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 100_000_000, 365 * DAY, 0, None), Err(LoanError::InvalidTerm));
+        assert!(contract.request_loan(&env, borrower.clone(), 100_000_000, 90 * DAY, 0, None).is_ok());
+    }
+
+    #[test]
+    fn test_get_config_reflects_setters() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert_eq!(contract.get_owner(), owner);
+
+        apply_change(&env, &mut contract, &owner, ParamChange::LoanLimits(20_000_000, 500_000_000));
+        apply_change(&env, &mut contract, &owner, ParamChange::OriginationFeeBps(150));
+        apply_change(&env, &mut contract, &owner, ParamChange::ReserveBps(1_000));
+        assert!(contract.set_savings_taper(&env, owner.clone(), 400, 100, 50_000_000).is_ok());
+        assert!(contract.set_min_repayment(&env, owner.clone(), 2_000_000).is_ok());
+
+        let config = contract.get_config();
+        assert_eq!(config.base_interest_rate, 5.0);
+        assert_eq!(config.min_loan_amount, 20_000_000);
+        assert_eq!(config.max_loan_amount, 500_000_000);
+        assert_eq!(config.origination_fee_bps, 150);
+        assert_eq!(config.reserve_bps, 1_000);
+        assert_eq!(config.savings_bps, 400);
+        assert_eq!(config.tapered_savings_bps, 100);
+        assert_eq!(config.reward_threshold, 50_000_000);
+        assert_eq!(config.min_repayment, 2_000_000);
+
+        let borrower = Address::random(&env);
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 600_000_000, TERM, 0, None), Err(LoanError::AmountOutOfRange));
+    }
+
+    #[test]
+    fn test_large_loan_needs_quorum() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let mut underwriters = Vec::new(&env);
+        let first = Address::random(&env);
+        let second = Address::random(&env);
+        underwriters.push_back(first.clone());
+        underwriters.push_back(second.clone());
+        underwriters.push_back(Address::random(&env));
+        assert!(contract.set_underwriters(&env, owner.clone(), underwriters, 2, 500_000_000).is_ok());
+
+        let borrower = Address::random(&env);
+        let small = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        assert_eq!(contract.loan_statement(&env, small).unwrap().status, LoanStatus::Active);
+
+        let funds_before = contract.pool.total_funds;
+        let large = contract.request_loan(&env, borrower.clone(), 800_000_000, TERM, 0, None).unwrap();
+        assert_eq!(contract.approve_loan(&env, first.clone(), large), Ok(LoanStatus::Pending));
+        assert_eq!(contract.approve_loan(&env, first.clone(), large), Err(LoanError::AlreadyApproved));
+        assert_eq!(contract.approve_loan(&env, borrower.clone(), large), Err(LoanError::NotUnderwriter));
+        assert_eq!(contract.pool.total_funds, funds_before);
+
+        assert_eq!(contract.approve_loan(&env, second.clone(), large), Ok(LoanStatus::Active));
+        assert_eq!(contract.pool.total_funds, funds_before - 800_000_000);
+        assert_eq!(contract.loan_statement(&env, large).unwrap().status, LoanStatus::Active);
+    }
+
+    #[test]
+    fn test_param_change_timelock() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        let id = contract.queue_param_change(&env, owner.clone(), ParamChange::OriginationFeeBps(200)).unwrap();
+
+        env.ledger().with_mut(|li| li.timestamp += PARAM_TIMELOCK_SECS - 1);
+        assert_eq!(contract.execute_param_change(&env, owner.clone(), id), Err(LoanError::TimelockActive));
+        assert_eq!(contract.get_config().origination_fee_bps, 0);
+
+        env.ledger().with_mut(|li| li.timestamp += 1);
+        assert!(contract.execute_param_change(&env, owner.clone(), id).is_ok());
+        assert_eq!(contract.get_config().origination_fee_bps, 200);
+        assert_eq!(contract.execute_param_change(&env, owner.clone(), id), Err(LoanError::ChangeNotFound));
+
+        let id = contract.queue_param_change(&env, owner.clone(), ParamChange::ReserveBps(500)).unwrap();
+        assert!(contract.cancel_param_change(&env, owner.clone(), id).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += PARAM_TIMELOCK_SECS);
+        assert_eq!(contract.execute_param_change(&env, owner.clone(), id), Err(LoanError::ChangeNotFound));
+        assert_eq!(contract.get_config().reserve_bps, 0);
+    }
+
+    #[test]
+    fn test_repay_on_behalf_credits_borrower() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let borrower = Address::random(&env);
+        let employer = Address::random(&env);
+        mint(&env, &contract, &employer, 200_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).unwrap();
+
+        assert!(contract.repay_on_behalf(&env, employer.clone(), borrower.clone(), loan_id, 200_000_000).is_ok());
+        let loan = contract.get_loan_status(borrower.clone()).unwrap();
+        assert_eq!(loan.repaid_amount, 200_000_000);
+        assert_eq!(loan.savings, 5_000_000);
+        assert_eq!(contract.loan_statement(&env, loan_id).unwrap().remaining_balance, 300_000_000);
+        assert_eq!(token::Client::new(&env, &contract.pool.token).balance(&employer), 0);
+
+        let stranger = Address::random(&env);
+        assert_eq!(
+            contract.repay_on_behalf(&env, employer.clone(), stranger, loan_id, 10_000_000),
+            Err(LoanError::LoanNotFound)
+        );
+
+        // A defaulted loan can still be paid down by someone else.
+        env.ledger().with_mut(|li| li.timestamp += 200 * DAY);
+        assert!(contract.mark_default(&env, owner.clone(), loan_id).is_ok());
+        mint(&env, &contract, &employer, 50_000_000);
+        assert!(contract.repay_on_behalf(&env, employer.clone(), borrower.clone(), loan_id, 50_000_000).is_ok());
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().repaid_amount, 250_000_000);
+    }
+
+    #[test]
+    fn test_capitalize_interest_up_to_cap() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_max_capitalization_bps(&env, owner.clone(), 100).is_ok());
+        let borrower = Address::random(&env);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 300_000_000, 90 * DAY, 0, None).unwrap();
+
+        env.ledger().with_mut(|li| li.timestamp = 20 * DAY);
+        assert_eq!(contract.capitalize_interest(&env, owner.clone(), loan_id), Err(LoanError::LoanCurrent));
+        // Past the first due date but still inside the grace period.
+        env.ledger().with_mut(|li| li.timestamp = 32 * DAY);
+        assert_eq!(contract.capitalize_interest(&env, owner.clone(), loan_id), Err(LoanError::LoanCurrent));
+
+        env.ledger().with_mut(|li| li.timestamp = 40 * DAY);
+        let capitalized = contract.capitalize_interest(&env, owner.clone(), loan_id).unwrap();
+        assert!(capitalized > 0);
+        assert_eq!(contract.capitalize_interest(&env, owner.clone(), loan_id), Err(LoanError::NothingToCapitalize));
+        let loan = contract.get_loan_status(borrower.clone()).unwrap();
+        assert_eq!(loan.amount, 300_000_000 + capitalized);
+        assert_eq!(loan.accrued_interest, loan.interest_paid);
+
+        // Still overdue, so the next ten days accrue at the penalty rate on the larger base.
+        let before = contract.loan_statement(&env, loan_id).unwrap().interest_accrued;
+        env.ledger().with_mut(|li| li.timestamp = 50 * DAY);
+        let after = contract.loan_statement(&env, loan_id).unwrap().interest_accrued;
+        let expected = (loan.amount as i128 * 750 * (10 * DAY) as i128 / (10_000 * 31_536_000)) as i64;
+        assert_eq!(after - before, expected);
+
+        env.ledger().with_mut(|li| li.timestamp = 80 * DAY);
+        assert_eq!(
+            contract.capitalize_interest(&env, owner.clone(), loan_id),
+            Err(LoanError::CapitalizationCapExceeded)
+        );
+    }
+
+    #[test]
+    fn test_reward_never_drops_rate_below_floor() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_min_loan_rate_bps(&env, owner.clone(), 400).is_ok());
+        assert!(contract.set_savings_taper(&env, owner.clone(), 500, 500, 1_000_000).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 10_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 1_000_000_000, TERM, 0, None).is_ok());
+        assert_eq!(contract.get_loan_status(borrower.clone()).unwrap().min_rate_bps, 400);
+
+        for _ in 0..9 {
+            assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+            assert!(contract.get_loan_status(borrower.clone()).unwrap().interest_rate >= 4.0);
+        }
+        assert_eq!(contract.get_loan_status(borrower.clone()).unwrap().interest_rate, 4.0);
+    }
+
+    #[test]
+    fn test_batch_accrue_is_idempotent_within_ledger() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let first = Address::random(&env);
+        let second = Address::random(&env);
+        assert!(contract.request_loan(&env, first.clone(), 500_000_000, TERM, 0, None).is_ok());
+        assert!(contract.request_loan(&env, second.clone(), 300_000_000, TERM, 0, None).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += 20 * DAY);
+
+        assert_eq!(contract.batch_accrue(&env, first.clone(), 0, 10), Err(LoanError::Unauthorized));
+        assert_eq!(contract.batch_accrue(&env, owner.clone(), 0, 10), Ok(2));
+        let accrued = contract.get_loan_status(first.clone()).unwrap().accrued_interest;
+        assert!(accrued > 0);
+        assert_eq!(contract.batch_accrue(&env, owner.clone(), 0, 10), Ok(2));
+        assert_eq!(contract.get_loan_status(first.clone()).unwrap().accrued_interest, accrued);
+
+        // Pagination: only the second loan's window is touched.
+        env.ledger().with_mut(|li| li.timestamp += 10 * DAY);
+        assert_eq!(contract.batch_accrue(&env, owner.clone(), 1, 1), Ok(1));
+        assert_eq!(contract.get_loan_status(first.clone()).unwrap().accrued_interest, accrued);
+        assert_eq!(contract.get_loan_status(second.clone()).unwrap().last_accrual_ts, env.ledger().timestamp());
+    }
+
+    #[test]
+    fn test_disbursement_delay() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_disbursement_delay_secs(&env, owner.clone(), 3 * DAY).is_ok());
+        let borrower = Address::random(&env);
+        let token = token::Client::new(&env, &contract.pool.token);
+        let funds_before = contract.pool.total_funds;
+
+        let loan_id = contract.request_loan(&env, borrower.clone(), 200_000_000, TERM, 0, None).unwrap();
+        assert_eq!(contract.loan_statement(&env, loan_id).unwrap().status, LoanStatus::ReadyToDisburse);
+        assert!(contract.repay_loan(&env, borrower.clone(), 1_000_000, None).is_err());
+
+        env.ledger().with_mut(|li| li.timestamp += 2 * DAY);
+        assert_eq!(contract.disburse(&env, borrower.clone(), loan_id), Err(LoanError::DisbursementDelayActive));
+        assert_eq!(token.balance(&borrower), 0);
+        assert_eq!(contract.pool.total_funds, funds_before);
+
+        env.ledger().with_mut(|li| li.timestamp += DAY);
+        assert!(contract.disburse(&env, borrower.clone(), loan_id).is_ok());
+        assert_eq!(token.balance(&borrower), 200_000_000);
+        assert_eq!(contract.pool.total_funds, funds_before - 200_000_000);
+        let loan = contract.get_loan_status(borrower.clone()).unwrap();
+        assert_eq!(loan.status, LoanStatus::Active);
+        assert_eq!(loan.originated_at, env.ledger().timestamp());
+        assert_eq!(contract.disburse(&env, borrower.clone(), loan_id), Err(LoanError::LoanNotFound));
+    }
+
+    #[test]
+    fn test_loan_events_history() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += DAY);
+        assert!(contract.repay_loan(&env, borrower.clone(), 40_000_000, None).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += DAY);
+        let remaining = contract.loan_statement(&env, loan_id).unwrap().remaining_balance;
+        assert!(contract.repay_loan(&env, borrower.clone(), remaining, None).is_ok());
+
+        let events = contract.get_loan_events(&env, loan_id);
+        assert_eq!(events.len(), 4);
+        assert_eq!(events.get(0).unwrap(), LoanEvent { ts: 1_000, kind: LoanEventKind::Originated, amount: 100_000_000 });
+        assert_eq!(events.get(1).unwrap(), LoanEvent { ts: 1_000 + DAY, kind: LoanEventKind::Repayment, amount: 40_000_000 });
+        assert_eq!(events.get(2).unwrap(), LoanEvent { ts: 1_000 + 2 * DAY, kind: LoanEventKind::Repayment, amount: remaining });
+        assert_eq!(events.get(3).unwrap().kind, LoanEventKind::Closed);
+        assert_eq!(events.get(3).unwrap().amount, 40_000_000 + remaining);
+        assert_eq!(contract.get_loan_events(&env, 99).len(), 0);
+    }
+
+    #[test]
+    fn test_base_rate_vote() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let lender = Address::random(&env);
+        mint(&env, &contract, &lender, 1_000_000_000);
+        assert!(contract.deposit(&env, lender.clone(), 1_000_000_000).is_ok());
+
+        let passing = contract.propose_base_rate(&env, owner.clone(), 700).unwrap();
+        assert!(contract.vote(&env, owner.clone(), passing, true).is_ok());
+        assert_eq!(contract.vote(&env, owner.clone(), passing, true), Err(LoanError::AlreadyVoted));
+        assert!(contract.vote(&env, lender.clone(), passing, false).is_ok());
+        assert_eq!(contract.finalize_proposal(&env, passing), Err(LoanError::VotingOpen));
+
+        let failing = contract.propose_base_rate(&env, lender.clone(), 200).unwrap();
+        assert!(contract.vote(&env, lender.clone(), failing, true).is_ok());
+        assert!(contract.vote(&env, owner.clone(), failing, false).is_ok());
+        assert_eq!(contract.propose_base_rate(&env, Address::random(&env), 100), Err(LoanError::InsufficientShares));
+
+        env.ledger().with_mut(|li| li.timestamp += VOTING_PERIOD_SECS);
+        assert_eq!(contract.vote(&env, Address::random(&env), passing, true), Err(LoanError::VotingClosed));
+        assert_eq!(contract.finalize_proposal(&env, passing), Ok(true));
+        assert_eq!(contract.get_config().base_interest_rate, 7.0);
+        assert_eq!(contract.finalize_proposal(&env, failing), Ok(false));
+        assert_eq!(contract.get_config().base_interest_rate, 7.0);
+        assert_eq!(contract.finalize_proposal(&env, failing), Err(LoanError::ProposalNotFound));
+    }
+
+    #[test]
+    fn test_loan_records_denomination() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let token = contract.pool.token.clone();
+        let symbol = token::Client::new(&env, &token).symbol();
+        let borrower = Address::random(&env);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+
+        let loan = contract.get_loan_status(borrower.clone()).unwrap();
+        assert_eq!(loan.denom, token);
+        assert_eq!(loan.symbol, symbol);
+        let statement = contract.loan_statement(&env, loan_id).unwrap();
+        assert_eq!(statement.denom, token);
+        assert_eq!(statement.symbol, symbol);
+        assert_eq!(contract.pool.token_symbols.get(token).unwrap(), symbol);
+    }
+
+    #[test]
+    fn test_leaderboards() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let small = Address::random(&env);
+        let large = Address::random(&env);
+        let repeat = Address::random(&env);
+        assert!(contract.request_loan(&env, small.clone(), 100_000_000, TERM, 0, None).is_ok());
+        assert!(contract.request_loan(&env, large.clone(), 400_000_000, TERM, 0, None).is_ok());
+        assert!(contract.request_loan(&env, repeat.clone(), 200_000_000, TERM, 0, None).is_ok());
+        mint(&env, &contract, &repeat, 1_000_000_000);
+        let remaining = contract.loan_statement(&env, 3).unwrap().remaining_balance;
+        assert!(contract.repay_loan(&env, repeat.clone(), remaining, None).is_ok());
+        assert!(contract.request_loan(&env, repeat.clone(), 300_000_000, TERM, 0, None).is_ok());
+
+        let borrowers = contract.top_borrowers(&env, 2);
+        assert_eq!(borrowers.len(), 2);
+        assert_eq!(borrowers.get(0).unwrap(), (repeat.clone(), 500_000_000));
+        assert_eq!(borrowers.get(1).unwrap(), (large.clone(), 400_000_000));
+        assert_eq!(contract.top_borrowers(&env, 10).len(), 3);
+
+        let first = Address::random(&env);
+        let second = Address::random(&env);
+        mint(&env, &contract, &first, 1_000_000_000);
+        mint(&env, &contract, &second, 1_000_000_000);
+        assert!(contract.deposit(&env, first.clone(), 100_000_000).is_ok());
+        assert!(contract.deposit(&env, second.clone(), 150_000_000).is_ok());
+        assert!(contract.deposit(&env, first.clone(), 100_000_000).is_ok());
+        let depositors = contract.top_depositors(&env, 5);
+        assert_eq!(depositors.len(), 2);
+        assert_eq!(depositors.get(0).unwrap(), (first, 200_000_000));
+        assert_eq!(depositors.get(1).unwrap(), (second, 150_000_000));
+        assert_eq!(contract.top_depositors(&env, 0).len(), 0);
+    }
+
+    #[test]
+    fn test_circuit_breaker_trips_on_outflow_burst() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_circuit_breaker(&env, owner.clone(), 2_000_000_000, DAY).is_ok());
+        for _ in 0..2 {
+            assert!(contract.request_loan(&env, Address::random(&env), 1_000_000_000, TERM, 0, None).is_ok());
+        }
+        assert!(!contract.is_paused());
+        assert!(contract.request_loan(&env, Address::random(&env), 500_000_000, TERM, 0, None).is_ok());
+        assert!(contract.is_paused());
+
+        let borrower = Address::random(&env);
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None), Err(LoanError::LendingPaused));
+        env.ledger().with_mut(|li| li.timestamp += 2 * DAY);
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None), Err(LoanError::LendingPaused));
+
+        assert!(contract.resume_lending(&env, Address::random(&env)).is_err());
+        assert!(contract.resume_lending(&env, owner.clone()).is_ok());
+        assert!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).is_ok());
+    }
+
+    #[test]
+    fn test_loyalty_discount_for_repeat_borrower() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        apply_change(&env, &mut contract, &owner, ParamChange::OriginationFeeBps(100));
+        assert!(contract.set_loyalty_discount(&env, owner.clone(), 20, 50).is_ok());
+        let repeat = Address::random(&env);
+        mint(&env, &contract, &repeat, 1_000_000_000);
+        for _ in 0..2 {
+            let loan_id = contract.request_loan(&env, repeat.clone(), 100_000_000, TERM, 0, None).unwrap();
+            let remaining = contract.loan_statement(&env, loan_id).unwrap().remaining_balance;
+            assert!(contract.repay_loan(&env, repeat.clone(), remaining, None).is_ok());
+        }
+
+        let token = token::Client::new(&env, &contract.pool.token);
+        let newcomer = Address::random(&env);
+        assert!(contract.request_loan(&env, newcomer.clone(), 100_000_000, TERM, 0, None).is_ok());
+        assert_eq!(token.balance(&newcomer), 99_000_000);
+
+        let before = token.balance(&repeat);
+        assert!(contract.request_loan(&env, repeat.clone(), 100_000_000, TERM, 0, None).is_ok());
+        assert_eq!(token.balance(&repeat) - before, 99_400_000);
+    }
+
+    #[test]
+    fn test_estimate_payoff_at() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        let borrower = Address::random(&env);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += 10 * DAY);
+        let now = env.ledger().timestamp();
+
+        let current = contract.estimate_payoff_at(&env, loan_id, now).unwrap();
+        assert_eq!(current, contract.loan_statement(&env, loan_id).unwrap().remaining_balance);
+        let in_ten_days = contract.estimate_payoff_at(&env, loan_id, now + 10 * DAY).unwrap();
+        let overdue = contract.estimate_payoff_at(&env, loan_id, now + 60 * DAY).unwrap();
+        assert!(current < in_ten_days && in_ten_days < overdue);
+        // Past the first installment plus grace, interest accrues faster.
+        assert!(overdue - in_ten_days > 5 * (in_ten_days - current));
+        assert_eq!(contract.estimate_payoff_at(&env, loan_id, now - 1), Err(LoanError::InvalidParameter));
+        assert_eq!(contract.loan_statement(&env, loan_id).unwrap().remaining_balance, current);
+    }
+
+    #[test]
+    fn test_rounding_mode_on_accrued_interest() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let loan_id = contract.request_loan(&env, Address::random(&env), 500_000_000, TERM, 0, None).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += 7 * DAY);
+
+        let down = contract.loan_statement(&env, loan_id).unwrap().interest_accrued;
+        assert!(contract.set_rounding_mode(&env, owner.clone(), RoundingMode::Up).is_ok());
+        let up = contract.loan_statement(&env, loan_id).unwrap().interest_accrued;
+        assert!(contract.set_rounding_mode(&env, owner.clone(), RoundingMode::Nearest).is_ok());
+        let nearest = contract.loan_statement(&env, loan_id).unwrap().interest_accrued;
+
+        // 500_000_000 at 5% for 7 days is 479_452.05 stroops.
+        assert_eq!(down, 479_452);
+        assert_eq!(up, down + 1);
+        assert_eq!(nearest, down);
+        assert_eq!(contract.get_config().rounding_mode, RoundingMode::Nearest);
+    }
+
+    #[test]
+    fn test_reinstated_borrower_can_borrow_again() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        assert_eq!(contract.mark_default(&env, owner.clone(), loan_id), Err(LoanError::LoanCurrent));
+
+        env.ledger().with_mut(|li| li.timestamp += 40 * DAY);
+        assert!(contract.mark_default(&env, owner.clone(), loan_id).is_ok());
+        assert_eq!(contract.get_credit_score(borrower.clone()), DEFAULT_CREDIT_SCORE - DEFAULT_SCORE_PENALTY);
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None), Err(LoanError::BorrowerBlacklisted));
+
+        // Defaulted loans stop accruing; a catch-up payment settles them.
+        let owed = contract.loan_statement(&env, loan_id).unwrap().remaining_balance;
+        env.ledger().with_mut(|li| li.timestamp += 10 * DAY);
+        assert_eq!(contract.loan_statement(&env, loan_id).unwrap().remaining_balance, owed);
+        assert!(contract.repay_loan(&env, borrower.clone(), owed / 2, None).is_ok());
+        assert_eq!(contract.reinstate_loan(&env, owner.clone(), loan_id), Err(LoanError::BalanceOutstanding));
+        assert!(contract.repay_loan(&env, borrower.clone(), owed - owed / 2, None).is_ok());
+        assert_eq!(contract.loan_statement(&env, loan_id).unwrap().status, LoanStatus::Defaulted);
+
+        assert!(contract.reinstate_loan(&env, owner.clone(), loan_id).is_ok());
+        assert_eq!(contract.loan_statement(&env, loan_id).unwrap().status, LoanStatus::Repaid);
+        assert_eq!(
+            contract.get_credit_score(borrower.clone()),
+            DEFAULT_CREDIT_SCORE - DEFAULT_SCORE_PENALTY + REINSTATE_SCORE_RECOVERY
+        );
+        assert_eq!(contract.reinstate_loan(&env, owner.clone(), loan_id), Err(LoanError::LoanNotDefaulted));
+        assert!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).is_ok());
+    }
+
+    #[test]
+    fn test_blend_tops_up_pool_funding() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let blend = env.register_contract(None, MockBlend);
+        let blend_client = MockBlendClient::new(&env, &blend);
+        blend_client.init(&contract.pool.token);
+        mint(&env, &contract, &blend, 1_000_000_000);
+        let borrower = Address::random(&env);
+        assert!(contract.withdraw(&env, owner.clone(), 9_800_000_000).is_ok());
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None), Err(LoanError::InsufficientFunds));
+
+        assert!(contract.set_blend_pool(&env, owner.clone(), Some(blend.clone())).is_ok());
+        assert!(contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+        let loan = contract.get_loan_status(borrower.clone()).unwrap();
+        assert_eq!(loan.blend_funded, 300_000_000);
+        assert_eq!(contract.pool.total_funds, 0);
+        assert_eq!(token::Client::new(&env, &contract.pool.token).balance(&borrower), 500_000_000);
+
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        // 5% of the payment is set aside for savings and insurance; the rest repays Blend.
+        assert_eq!(contract.get_loan_status(borrower.clone()).unwrap().blend_funded, 205_000_000);
+        assert_eq!(blend_client.repaid(), 95_000_000);
+        assert_eq!(contract.pool.total_funds, 0);
+        assert_eq!(contract.set_blend_pool(&env, owner.clone(), None), Err(LoanError::BlendBalanceOutstanding));
+    }
+
+    #[test]
+    fn test_health_check_flags_corruption() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        assert!(contract.health_check(&env).iter().all(|(_, ok)| ok));
+
+        contract.pool.total_shares += 1;
+        let checks = contract.health_check(&env);
+        assert_eq!(checks.len(), 5);
+        for (name, ok) in checks.iter() {
+            assert_eq!(ok, name != symbol_short!("shares") && name != symbol_short!("backing"));
+        }
+
+        contract.pool.total_shares -= 1;
+        let mut loan = contract.pool.loans.get(1).unwrap();
+        loan.repaid_amount = loan.amount * 2;
+        contract.pool.loans.set(1, loan);
+        let checks = contract.health_check(&env);
+        assert!(checks.iter().any(|(name, ok)| name == symbol_short!("loans") && !ok));
+    }
+
+    #[test]
+    fn test_savings_returned_at_payoff_by_preference() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let token = token::Client::new(&env, &contract.pool.token);
+        let manual = Address::random(&env);
+        let auto = Address::random(&env);
+        assert!(contract.set_auto_return_savings(&env, auto.clone(), true).is_ok());
+
+        for borrower in [manual.clone(), auto.clone()] {
+            mint(&env, &contract, &borrower, 100_000_000);
+            let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+            assert_eq!(contract.withdraw_savings(&env, borrower.clone(), loan_id), Err(LoanError::LoanNotRepaid));
+            assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        }
+
+        // Half of the 5% set aside was saved: the auto borrower has it back already.
+        assert_eq!(token.balance(&auto), 102_500_000);
+        assert_eq!(contract.loan_statement(&env, 2).unwrap().savings, 0);
+        assert_eq!(token.balance(&manual), 100_000_000);
+        assert_eq!(contract.loan_statement(&env, 1).unwrap().savings, 2_500_000);
+        assert_eq!(contract.withdraw_savings(&env, manual.clone(), 1), Ok(2_500_000));
+        assert_eq!(token.balance(&manual), 102_500_000);
+        assert_eq!(contract.withdraw_savings(&env, manual.clone(), 1), Ok(0));
+    }
+
+    #[test]
+    fn test_live_outstanding_stats_include_unbooked_interest() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.request_loan(&env, Address::random(&env), 1_000_000_000, TERM, 0, None).is_ok());
+        assert_eq!(contract.outstanding_stats(&env, true), contract.outstanding_stats(&env, false));
+
+        env.ledger().with_mut(|li| li.timestamp += 20 * DAY);
+        let (raw, raw_utilization) = contract.outstanding_stats(&env, false);
+        let (live, live_utilization) = contract.outstanding_stats(&env, true);
+        assert_eq!(raw, 1_000_000_000);
+        assert!(live > raw);
+        assert!(live_utilization >= raw_utilization);
+
+        assert_eq!(contract.batch_accrue(&env, owner.clone(), 0, 10), Ok(1));
+        assert_eq!(contract.outstanding_stats(&env, false).0, live);
+    }
+
+    #[test]
+    fn test_loans_keyed_by_id_and_capped() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_max_loans(&env, owner.clone(), 2).is_ok());
+        let first = Address::random(&env);
+        let second = Address::random(&env);
+        mint(&env, &contract, &first, 1_000_000_000);
+        let first_id = contract.request_loan(&env, first.clone(), 100_000_000, TERM, 0, None).unwrap();
+        let second_id = contract.request_loan(&env, second.clone(), 200_000_000, TERM, 0, None).unwrap();
+        assert_eq!(contract.request_loan(&env, Address::random(&env), 100_000_000, TERM, 0, None), Err(LoanError::LoanCapReached));
+        assert_eq!(contract.can_borrow(&env, Address::random(&env), 100_000_000), Err(LoanError::LoanCapReached));
+
+        // Updates go straight to the keyed entry and leave others alone.
+        assert!(contract.repay_on_behalf(&env, first.clone(), first.clone(), first_id, 50_000_000).is_ok());
+        assert_eq!(contract.get_loan_by_id(first_id).unwrap().repaid_amount, 50_000_000);
+        assert_eq!(contract.get_loan_by_id(second_id).unwrap().repaid_amount, 0);
+        assert_eq!(contract.get_loan_by_id(second_id).unwrap().borrower, second);
+        assert!(contract.get_loan_by_id(99).is_none());
+    }
+
+    #[test]
+    fn test_no_rate_reward_while_delinquent() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_savings_taper(&env, owner.clone(), 500, 500, 2_000_000).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 1_000_000_000, TERM, 0, None).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += 40 * DAY);
+
+        assert!(contract.repay_loan(&env, borrower.clone(), 80_000_000, None).is_ok());
+        let loan = contract.get_loan_status(borrower.clone()).unwrap();
+        assert!(loan.savings >= 2_000_000);
+        assert_eq!(loan.interest_rate, 5.0);
+
+        // Covering the rest of the missed installment brings the loan current.
+        assert!(contract.repay_loan(&env, borrower.clone(), 10_000_000, None).is_ok());
+        assert_eq!(contract.get_loan_status(borrower.clone()).unwrap().interest_rate, 4.5);
+    }
+
+    #[test]
+    fn test_transfer_loan_to_new_borrower() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let from = Address::random(&env);
+        let to = Address::random(&env);
+        mint(&env, &contract, &to, 1_000_000_000);
+        assert!(contract.set_credit_score(&env, owner.clone(), to.clone(), 760).is_ok());
+        let loan_id = contract.request_loan(&env, from.clone(), 300_000_000, TERM, 0, None).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += 10 * DAY);
+        assert!(contract.repay_loan(&env, from.clone(), 50_000_000, None).is_ok());
+        let savings = contract.get_loan_by_id(loan_id).unwrap().savings;
+        assert!(savings > 0);
+        let token = token::Client::new(&env, &contract.pool.token);
+        let from_balance = token.balance(&from);
+
+        assert_eq!(contract.transfer_loan(&env, from.clone(), from.clone(), loan_id, false), Err(LoanError::SelfTransfer));
+        assert!(contract.transfer_loan(&env, from.clone(), to.clone(), loan_id, true).is_ok());
+        let loan = contract.get_loan_by_id(loan_id).unwrap();
+        assert_eq!(loan.borrower, to);
+        // The old borrower's savings go back to them rather than with the loan.
+        assert_eq!(loan.savings, 0);
+        assert_eq!(token.balance(&from), from_balance + savings as i128);
+        assert_eq!(contract.pool.savings_totals.get(to.clone()), None);
+        // One point below the pool rate, which is a touch over 5% here.
+        assert!(loan.interest_rate > 4.0 && loan.interest_rate < 4.1);
+        assert!(loan.accrued_interest > 0);
+
+        assert_eq!(contract.repay_loan(&env, from.clone(), 50_000_000, None), Err(LoanError::LoanNotFound));
+        assert!(contract.repay_loan(&env, to.clone(), 50_000_000, None).is_ok());
+        assert_eq!(contract.get_loan_status(to.clone()).unwrap().repaid_amount, 100_000_000);
+        assert!(contract.get_loan_status(from.clone()).is_none());
+    }
+
+    #[test]
+    fn test_solvency_ratio_moves_with_lending() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        assert_eq!(contract.solvency_ratio(&env), u32::MAX);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+
+        assert!(contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+        let one_loan = contract.solvency_ratio(&env);
+        assert!(contract.request_loan(&env, Address::random(&env), 500_000_000, TERM, 0, None).is_ok());
+        let two_loans = contract.solvency_ratio(&env);
+        assert!(two_loans < one_loan);
+
+        assert!(contract.repay_loan(&env, borrower.clone(), 200_000_000, None).is_ok());
+        assert!(contract.solvency_ratio(&env) > two_loans);
+    }
+
+    #[test]
+    fn test_default_cooldown_outlasts_reinstatement() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_default_cooldown_secs(&env, owner.clone(), 90 * DAY).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += 40 * DAY);
+        assert!(contract.mark_default(&env, owner.clone(), loan_id).is_ok());
+        let defaulted = env.ledger().timestamp();
+
+        let owed = contract.loan_statement(&env, loan_id).unwrap().remaining_balance;
+        assert!(contract.repay_loan(&env, borrower.clone(), owed, None).is_ok());
+        assert!(contract.reinstate_loan(&env, owner.clone(), loan_id).is_ok());
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None), Err(LoanError::DefaultCooldownActive));
+
+        env.ledger().with_mut(|li| li.timestamp = defaulted + 90 * DAY - 1);
+        assert_eq!(contract.can_borrow(&env, borrower.clone(), 100_000_000), Err(LoanError::DefaultCooldownActive));
+        env.ledger().with_mut(|li| li.timestamp = defaulted + 90 * DAY);
+        assert!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).is_ok());
+    }
+
+    #[test]
+    fn test_set_token_needs_clean_book() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let new_token = env.register_stellar_asset_contract(owner.clone());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        assert_eq!(contract.set_token(&env, owner.clone(), new_token.clone()), Err(LoanError::LoansOutstanding));
+
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        assert_eq!(contract.set_token(&env, owner.clone(), new_token.clone()), Err(LoanError::TokenBalanceRemaining));
+
+        let idle = contract.pool.total_funds;
+        assert!(contract.withdraw(&env, owner.clone(), idle).is_ok());
+        assert!(contract.withdraw_savings(&env, borrower.clone(), loan_id).is_ok());
+        assert!(contract.set_token(&env, Address::random(&env), new_token.clone()).is_err());
+        assert!(contract.set_token(&env, owner.clone(), new_token.clone()).is_ok());
+        assert_eq!(contract.pool.token, new_token);
+    }
+
+    #[test]
+    fn test_rate_reward_is_path_independent() {
+        let env = Env::default();
+        let (mut lump, owner) = setup(&env);
+        let (mut installments, other_owner) = setup(&env);
+        assert!(lump.set_savings_taper(&env, owner.clone(), 500, 500, 1_000_000).is_ok());
+        assert!(installments.set_savings_taper(&env, other_owner.clone(), 500, 500, 1_000_000).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &lump, &borrower, 1_000_000_000);
+        mint(&env, &installments, &borrower, 1_000_000_000);
+        assert!(lump.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+        assert!(installments.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+
+        assert!(lump.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        for _ in 0..10 {
+            assert!(installments.repay_loan(&env, borrower.clone(), 10_000_000, None).is_ok());
+        }
+        let lump_loan = lump.get_loan_status(borrower.clone()).unwrap();
+        let installments_loan = installments.get_loan_status(borrower.clone()).unwrap();
+        assert_eq!(lump_loan.savings, installments_loan.savings);
+        assert_eq!(lump_loan.interest_rate, installments_loan.interest_rate);
+        assert_eq!(lump_loan.interest_rate, lump.rate_for_savings(lump_loan.base_rate, 2_500_000));
+    }
+
+    #[test]
+    fn test_settle_below_outstanding_balance() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += 40 * DAY);
+        assert!(contract.mark_default(&env, owner.clone(), loan_id).is_ok());
+
+        let bureau = env.register_contract(None, MockBureau);
+        assert!(contract.set_credit_bureau(&env, owner.clone(), Some(bureau.clone())).is_ok());
+
+        let owed = contract.loan_statement(&env, loan_id).unwrap().remaining_balance;
+        let funds_before = contract.pool.total_funds;
+        assert_eq!(contract.close_and_settle(&env, owner.clone(), loan_id, owed + 1), Err(LoanError::InvalidAmount));
+        assert_eq!(contract.close_and_settle(&env, owner.clone(), loan_id, 60_000_000), Ok(owed - 60_000_000));
+        assert_eq!(MockBureauClient::new(&env, &bureau).last_report(), Some((borrower.clone(), loan_id, true)));
+
+        let loan = contract.get_loan_by_id(loan_id).unwrap();
+        assert_eq!(loan.status, LoanStatus::Repaid);
+        assert_eq!(contract.pool.total_funds, funds_before + 60_000_000);
+        let closed = contract.get_loan_events(&env, loan_id).last().unwrap();
+        assert_eq!(closed.kind, LoanEventKind::Closed);
+        assert_eq!(closed.amount, 60_000_000);
+        assert_eq!(contract.close_and_settle(&env, owner.clone(), loan_id, 0), Err(LoanError::LoanNotFound));
+    }
+
+    #[test]
+    fn test_aggregates_match_for_identical_pools() {
+        let env = Env::default();
+        let (mut first, _) = setup(&env);
+        let (mut second, _) = setup(&env);
+        let borrowers = [Address::random(&env), Address::random(&env), Address::random(&env)];
+        for contract in [&mut first, &mut second] {
+            for (i, borrower) in borrowers.iter().enumerate() {
+                mint(&env, contract, borrower, 1_000_000_000);
+                assert!(contract.request_loan(&env, borrower.clone(), 100_000_000 * (i as i64 + 1), TERM, 0, None).is_ok());
+            }
+        }
+        env.ledger().with_mut(|li| li.timestamp += 10 * DAY);
+        for contract in [&mut first, &mut second] {
+            assert!(contract.repay_loan(&env, borrowers[1].clone(), 30_000_000, None).is_ok());
+            assert!(contract.repay_loan(&env, borrowers[0].clone(), 20_000_000, None).is_ok());
+        }
+
+        let loans = first.loans();
+        for i in 1..loans.len() {
+            assert!(loans.get(i - 1).unwrap().id < loans.get(i).unwrap().id);
+        }
+        assert_eq!(first.get_pool_stats(), second.get_pool_stats());
+        assert_eq!(first.outstanding_stats(&env, true), second.outstanding_stats(&env, true));
+        assert_eq!(first.solvency_ratio(&env), second.solvency_ratio(&env));
+        assert_eq!(first.portfolio_by_band(&env), second.portfolio_by_band(&env));
+        assert_eq!(first.calculate_interest_rate(), second.calculate_interest_rate());
+    }
+
+    #[test]
+    fn test_withdraw_all_limited_by_liquidity() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let lender = Address::random(&env);
+        mint(&env, &contract, &lender, 2_000_000_000);
+        assert!(contract.deposit(&env, lender.clone(), 1_000_000_000).is_ok());
+        assert_eq!(contract.withdraw_all(&env, lender.clone()), Ok(1_000_000_000));
+        assert_eq!(contract.get_shares(lender.clone()), 0);
+        assert_eq!(contract.withdraw_all(&env, lender.clone()), Err(LoanError::InsufficientShares));
+
+        // Drain the pool down to the lender's deposit and lend most of it out.
+        assert!(contract.deposit(&env, lender.clone(), 1_000_000_000).is_ok());
+        assert!(contract.withdraw(&env, owner.clone(), 9_500_000_000).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 100_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 1_000_000_000, TERM, 0, None).is_ok());
+        let idle = contract.pool.total_funds;
+        assert_eq!(contract.withdraw_all(&env, lender.clone()), Ok(idle));
+        assert_eq!(contract.get_shares(lender.clone()), 1_000_000_000 - idle);
+        assert_eq!(contract.pool.total_funds, 0);
+    }
+
+    #[test]
+    fn test_monthly_compounding_beats_simple_interest() {
+        let env = Env::default();
+        let (mut simple, _) = setup(&env);
+        let (mut compounding, owner) = setup(&env);
+        assert_eq!(compounding.set_compounding_period_secs(&env, owner.clone(), 60), Err(LoanError::InvalidParameter));
+        assert!(compounding.set_compounding_period_secs(&env, owner.clone(), 30 * DAY).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &simple, &borrower, 1_000_000_000);
+        mint(&env, &compounding, &borrower, 1_000_000_000);
+        let simple_id = simple.request_loan(&env, borrower.clone(), 1_000_000_000, TERM, 0, None).unwrap();
+        let compounding_id = compounding.request_loan(&env, borrower.clone(), 1_000_000_000, TERM, 0, None).unwrap();
+        assert_eq!(simple.get_loan_by_id(simple_id).unwrap().interest_rate, compounding.get_loan_by_id(compounding_id).unwrap().interest_rate);
+
+        let year_end = env.ledger().timestamp() + TERM;
+        let simple_payoff = simple.estimate_payoff_at(&env, simple_id, year_end).unwrap();
+        let compounding_payoff = compounding.estimate_payoff_at(&env, compounding_id, year_end).unwrap();
+        assert!(compounding_payoff > simple_payoff);
+        assert!(compounding_payoff - simple_payoff < (simple_payoff - 1_000_000_000) / 10);
+    }
+
+    #[test]
+    fn test_savings_stop_at_cap() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_max_savings_per_loan(&env, owner.clone(), 3_000_000).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+
+        assert!(contract.repay_loan(&env, borrower.clone(), 80_000_000, None).is_ok());
+        assert_eq!(contract.get_loan_status(borrower.clone()).unwrap().savings, 2_000_000);
+        assert!(contract.repay_loan(&env, borrower.clone(), 80_000_000, None).is_ok());
+        assert_eq!(contract.get_loan_status(borrower.clone()).unwrap().savings, 3_000_000);
+
+        let funds_before = contract.pool.total_funds;
+        assert!(contract.repay_loan(&env, borrower.clone(), 80_000_000, None).is_ok());
+        let loan = contract.get_loan_status(borrower.clone()).unwrap();
+        assert_eq!(loan.savings, 3_000_000);
+        assert_eq!(loan.repaid_amount, 240_000_000);
+        assert_eq!(contract.pool.total_funds, funds_before + 80_000_000);
+    }
+
+    #[test]
+    fn test_origination_fees_follow_fee_collector() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        apply_change(&env, &mut contract, &owner, ParamChange::OriginationFeeBps(100));
+        let token = token::Client::new(&env, &contract.pool.token);
+        let first_collector = contract.pool.fee_collector.clone();
+        let first = Address::random(&env);
+        assert!(contract.request_loan(&env, first.clone(), 100_000_000, TERM, 0, None).is_ok());
+        assert_eq!(token.balance(&first_collector), 1_000_000);
+        assert_eq!(token.balance(&first), 99_000_000);
+
+        let treasury = Address::random(&env);
+        assert!(contract.set_fee_collector(&env, owner.clone(), treasury.clone()).is_ok());
+        let second = Address::random(&env);
+        assert!(contract.request_loan(&env, second.clone(), 200_000_000, TERM, 0, None).is_ok());
+        assert_eq!(token.balance(&treasury), 2_000_000);
+        assert_eq!(token.balance(&first_collector), 1_000_000);
+    }
+
+    #[test]
+    fn test_repayment_buckets_partition_each_payment() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_insurance_funding_bps(&env, owner.clone(), 3_000).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 1_000_000_000, TERM, 0, None).is_ok());
+
+        for amount in [100_000_000i64, 33_333_333, 7_000_001] {
+            let savings_before = contract.get_loan_status(borrower.clone()).unwrap().savings;
+            let insurance_before = contract.pool.insurance_fund;
+            let funds_before = contract.pool.total_funds;
+            assert!(contract.repay_loan(&env, borrower.clone(), amount, None).is_ok());
+            let saved = contract.get_loan_status(borrower.clone()).unwrap().savings - savings_before;
+            let insured = contract.pool.insurance_fund - insurance_before;
+            let pooled = contract.pool.total_funds - funds_before;
+            assert!(saved > 0 && insured > 0);
+            assert_eq!(saved + insured + pooled, amount);
+        }
+        assert!(contract.health_check(&env).iter().all(|(_, ok)| ok));
+    }
+
+    #[test]
+    fn test_contribute_to_insurance() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let token = token::Client::new(&env, &contract.pool.token);
+        let donor = Address::random(&env);
+        mint(&env, &contract, &donor, 50_000_000);
+        let insurance_before = contract.pool.insurance_fund;
+        let balance_before = token.balance(&env.current_contract_address());
+
+        assert!(contract.contribute_to_insurance(&env, donor.clone(), 50_000_000).is_ok());
+        assert_eq!(contract.pool.insurance_fund, insurance_before + 50_000_000);
+        assert_eq!(token.balance(&env.current_contract_address()), balance_before + 50_000_000);
+        assert_eq!(contract.contribute_to_insurance(&env, donor.clone(), 0), Err(LoanError::InvalidAmount));
+    }
+
+    #[test]
+    fn test_borrower_outstanding_sums_active_loans() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        let repaid_id = contract.request_loan(&env, borrower.clone(), 50_000_000, TERM, 0, None).unwrap();
+        assert!(contract.repay_loan(&env, borrower.clone(), 50_000_000, None).is_ok());
+        assert_eq!(contract.get_loan_by_id(repaid_id).unwrap().status, LoanStatus::Repaid);
+
+        assert!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).is_ok());
+        assert!(contract.request_loan(&env, borrower.clone(), 200_000_000, TERM, 0, None).is_ok());
+        assert!(contract.request_loan(&env, Address::random(&env), 300_000_000, TERM, 0, None).is_ok());
+        assert_eq!(contract.borrower_outstanding(borrower.clone()), 300_000_000);
+    }
+
+    #[test]
+    fn test_promo_loans_accrue_nothing_during_grace() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let start = env.ledger().timestamp();
+        assert!(contract.set_promo(&env, owner.clone(), start + 7 * DAY, 20 * DAY).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).unwrap();
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().promo_ends_at, start + 20 * DAY);
+
+        env.ledger().with_mut(|li| li.timestamp += 20 * DAY);
+        assert_eq!(contract.loan_statement(&env, loan_id).unwrap().remaining_balance, 500_000_000);
+        env.ledger().with_mut(|li| li.timestamp += 5 * DAY);
+        assert!(contract.loan_statement(&env, loan_id).unwrap().remaining_balance > 500_000_000);
+
+        // Loans taken once the promo has ended are priced normally from day one.
+        let late = Address::random(&env);
+        let late_id = contract.request_loan(&env, late.clone(), 500_000_000, TERM, 0, None).unwrap();
+        assert_eq!(contract.get_loan_by_id(late_id).unwrap().promo_ends_at, 0);
+        env.ledger().with_mut(|li| li.timestamp += DAY);
+        assert!(contract.loan_statement(&env, late_id).unwrap().remaining_balance > 500_000_000);
+    }
+
+    #[test]
+    fn test_archive_old_inactive_loan() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        let active_id = contract.request_loan(&env, Address::random(&env), 100_000_000, TERM, 0, None).unwrap();
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        assert_eq!(contract.archive_loan(&env, owner.clone(), loan_id), Err(LoanError::BalanceOutstanding));
+        assert!(contract.withdraw_savings(&env, borrower.clone(), loan_id).is_ok());
+        assert_eq!(contract.archive_loan(&env, owner.clone(), loan_id), Err(LoanError::RetentionPeriodActive));
+        assert_eq!(contract.archive_loan(&env, owner.clone(), active_id), Err(LoanError::LoanNotFound));
+
+        env.ledger().with_mut(|li| li.timestamp += ARCHIVE_RETENTION_SECS);
+        let record = contract.archive_loan(&env, owner.clone(), loan_id).unwrap();
+        assert_eq!(record.id, loan_id);
+        assert_eq!(record.borrower, borrower);
+        assert_eq!(record.status, LoanStatus::Repaid);
+        assert_eq!(record.repaid_amount, 100_000_000);
+        assert!(contract.get_loan_by_id(loan_id).is_none());
+        assert_eq!(contract.get_loan_events(&env, loan_id).len(), 0);
+        let listed = contract.list_loans(&env, 0, 10);
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed.get(0).unwrap().id, active_id);
+    }
+
+    #[test]
+    fn test_late_fees_stop_at_cap() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_late_fee_terms(&env, owner.clone(), 200, 500).is_ok());
+        let borrower = Address::random(&env);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        assert_eq!(contract.apply_late_fee(&env, owner.clone(), loan_id), Err(LoanError::LoanCurrent));
+
+        env.ledger().with_mut(|li| li.timestamp += 40 * DAY);
+        assert_eq!(contract.apply_late_fee(&env, owner.clone(), loan_id), Ok(2_000_000));
+        assert_eq!(contract.apply_late_fee(&env, owner.clone(), loan_id), Err(LoanError::LateFeeAlreadyCharged));
+        // One fee per missed installment, each a month apart.
+        env.ledger().with_mut(|li| li.timestamp += 30 * DAY);
+        assert_eq!(contract.apply_late_fee(&env, owner.clone(), loan_id), Ok(2_000_000));
+        env.ledger().with_mut(|li| li.timestamp += 30 * DAY);
+        assert_eq!(contract.apply_late_fee(&env, owner.clone(), loan_id), Ok(1_000_000));
+        env.ledger().with_mut(|li| li.timestamp += 30 * DAY);
+        assert_eq!(contract.apply_late_fee(&env, owner.clone(), loan_id), Ok(0));
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().accrued_fees, 5_000_000);
+    }
+
+    #[test]
+    fn test_migrated_loan_accrues_from_migration() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        let borrower = Address::random(&env);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        // A record from before accrual bookkeeping existed.
+        let mut loan = contract.pool.loans.get(loan_id).unwrap();
+        loan.accruing = false;
+        contract.pool.loans.set(loan_id, loan);
+
+        env.ledger().with_mut(|li| li.timestamp += 20 * DAY);
+        assert_eq!(contract.loan_statement(&env, loan_id).unwrap().remaining_balance, 100_000_000);
+        assert_eq!(contract.migrate_loan_to_accrual(&env, owner.clone(), loan_id), Ok(true));
+        env.ledger().with_mut(|li| li.timestamp += 10 * DAY);
+        assert_eq!(contract.migrate_loan_to_accrual(&env, owner.clone(), loan_id), Ok(false));
+
+        let rate_bps = (contract.get_loan_by_id(loan_id).unwrap().interest_rate * 100.0) as i128;
+        let interest = (100_000_000i128 * rate_bps * (10 * DAY) as i128 / (10_000 * 31_536_000)) as i64;
+        assert_eq!(contract.loan_statement(&env, loan_id).unwrap().remaining_balance, 100_000_000 + interest);
+    }
+
+    #[test]
+    fn test_utilization_bps_tracks_lending() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert_eq!(contract.get_utilization_bps(), 0);
+        assert!(contract.withdraw(&env, owner.clone(), 9_000_000_000).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+        let after_loan = contract.get_utilization_bps();
+        assert_eq!(after_loan, 10_000);
+
+        assert!(contract.repay_loan(&env, borrower.clone(), 200_000_000, None).is_ok());
+        assert!(contract.get_utilization_bps() < after_loan);
+
+        let idle = contract.pool.total_funds;
+        assert!(contract.request_loan(&env, Address::random(&env), idle, TERM, 0, None).is_ok());
+        assert_eq!(contract.pool.total_funds, 0);
+        assert_eq!(contract.get_utilization_bps(), UTILIZATION_CAP_BPS);
+    }
+
+    #[test]
+    fn test_credit_bureau_score_and_reports() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let bureau = env.register_contract(None, MockBureau);
+        let bureau_client = MockBureauClient::new(&env, &bureau);
+        let borrower = Address::random(&env);
+        let unknown = Address::random(&env);
+        bureau_client.set_score(&borrower, &900);
+        assert!(contract.set_credit_bureau(&env, owner.clone(), Some(bureau.clone())).is_ok());
+
+        // 600 internally and 850 (capped) at the bureau lands in the top band.
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        let known_rate = contract.get_loan_by_id(loan_id).unwrap().interest_rate;
+        let unknown_id = contract.request_loan(&env, unknown.clone(), 100_000_000, TERM, 0, None).unwrap();
+        let unknown_rate = contract.get_loan_by_id(unknown_id).unwrap().interest_rate;
+        assert!(unknown_rate - known_rate > 0.9);
+
+        env.ledger().with_mut(|li| li.timestamp += 40 * DAY);
+        assert!(contract.mark_default(&env, owner.clone(), loan_id).is_ok());
+        assert_eq!(bureau_client.last_report(), Some((borrower.clone(), loan_id, true)));
+    }
+
+    #[test]
+    fn test_min_collateral_at_origination() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let collateral_token = env.register_stellar_asset_contract(owner.clone());
+        apply_change(&env, &mut contract, &owner, ParamChange::CollateralToken(collateral_token.clone()));
+        assert!(contract.set_min_collateral_bps(&env, owner.clone(), 12_500).is_ok());
+        let borrower = Address::random(&env);
+        token::StellarAssetClient::new(&env, &collateral_token).mint(&borrower, &1_000_000_000);
+
+        assert_eq!(
+            contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 124_999_999, None),
+            Err(LoanError::InsufficientCollateral)
+        );
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 125_000_000, None).unwrap();
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().collateral, 125_000_000);
+        assert_eq!(token::Client::new(&env, &collateral_token).balance(&borrower), 875_000_000);
+    }
+
+    #[test]
+    fn test_band_lending_pause() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let risky = Address::random(&env);
+        let prime = Address::random(&env);
+        assert!(contract.set_credit_score(&env, owner.clone(), risky.clone(), 400).is_ok());
+        assert!(contract.set_credit_score(&env, owner.clone(), prime.clone(), 750).is_ok());
+        assert_eq!(contract.set_band_lending(&env, owner.clone(), 450, false), Err(LoanError::InvalidParameter));
+        assert!(contract.set_band_lending(&env, owner.clone(), 0, false).is_ok());
+
+        assert_eq!(contract.request_loan(&env, risky.clone(), 100_000_000, TERM, 0, None), Err(LoanError::BandLendingPaused));
+        assert!(contract.request_loan(&env, prime.clone(), 100_000_000, TERM, 0, None).is_ok());
+
+        assert!(contract.set_band_lending(&env, owner.clone(), 0, true).is_ok());
+        assert!(contract.request_loan(&env, risky.clone(), 100_000_000, TERM, 0, None).is_ok());
+    }
+
+    #[test]
+    fn test_loan_id_collision_rejected() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let first = Address::random(&env);
+        let loan_id = contract.request_loan(&env, first.clone(), 100_000_000, TERM, 0, None).unwrap();
+        contract.pool.next_loan_id = loan_id;
+
+        let second = Address::random(&env);
+        assert_eq!(contract.request_loan(&env, second.clone(), 200_000_000, TERM, 0, None), Err(LoanError::IdCollision));
+        let kept = contract.get_loan_by_id(loan_id).unwrap();
+        assert_eq!(kept.borrower, first);
+        assert_eq!(kept.amount, 100_000_000);
+        assert_eq!(contract.pool.next_loan_id, loan_id);
+    }
+
+    #[test]
+    fn test_savings_advance_needs_savings_history() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let saver = Address::random(&env);
+        mint(&env, &contract, &saver, 1_000_000_000);
+        let loan_id = contract.request_loan(&env, saver.clone(), 200_000_000, TERM, 0, None).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += 30 * DAY);
+        let payoff = contract.estimate_payoff_at(&env, loan_id, env.ledger().timestamp()).unwrap();
+        assert!(contract.repay_loan(&env, saver.clone(), payoff, None).is_ok());
+        // Withdrawn savings still count as saving history.
+        let saved = contract.withdraw_savings(&env, saver.clone(), loan_id).unwrap();
+        assert!(saved >= 5_000_000 && saved < 6_000_000);
+        env.ledger().with_mut(|li| li.timestamp += 30 * DAY);
+        // Advances carry no collateral, whatever the minimum for loans.
+        assert!(contract.set_min_collateral_bps(&env, owner.clone(), 5_000).is_ok());
+
+        // Just over 5M saved over two months supports an advance of up to 12.5M.
+        assert_eq!(contract.request_savings_advance(&env, saver.clone(), 20_000_000), Err(LoanError::InsufficientSavingsHistory));
+        let advance_id = contract.request_savings_advance(&env, saver.clone(), 12_000_000).unwrap();
+        let advance = contract.get_loan_by_id(advance_id).unwrap();
+        assert_eq!(advance.interest_rate, 5.0);
+        assert_eq!(advance.term_secs, ADVANCE_TERM_SECS);
+
+        let newcomer = Address::random(&env);
+        assert_eq!(contract.request_savings_advance(&env, newcomer, 12_000_000), Err(LoanError::InsufficientSavingsHistory));
+
+        // Archiving old loans doesn't shorten the saving history.
+        let payoff = contract.estimate_payoff_at(&env, advance_id, env.ledger().timestamp()).unwrap();
+        assert!(contract.repay_loan(&env, saver.clone(), payoff, None).is_ok());
+        assert!(contract.withdraw_savings(&env, saver.clone(), advance_id).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += ARCHIVE_RETENTION_SECS);
+        assert!(contract.archive_loan(&env, owner.clone(), loan_id).is_ok());
+        assert!(contract.archive_loan(&env, owner.clone(), advance_id).is_ok());
+        assert_eq!(contract.request_savings_advance(&env, saver.clone(), 10_000_000), Err(LoanError::InsufficientSavingsHistory));
+    }
+
+    #[test]
+    fn test_proceeds_disbursed_to_third_party() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        let token = token::Client::new(&env, &contract.pool.token);
+        let borrower = Address::random(&env);
+        let vendor = Address::random(&env);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, Some(vendor.clone())).unwrap();
+
+        assert_eq!(token.balance(&vendor), 100_000_000);
+        assert_eq!(token.balance(&borrower), 0);
+        let loan = contract.get_loan_by_id(loan_id).unwrap();
+        assert_eq!(loan.borrower, borrower);
+        assert_eq!(contract.borrower_outstanding(borrower.clone()), 100_000_000);
+        assert_eq!(contract.borrower_outstanding(vendor.clone()), 0);
+    }
+
+    #[test]
+    fn test_reconcile_shows_direct_transfer_as_surplus() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let (accounted, actual) = contract.reconcile(&env, owner.clone()).unwrap();
+        assert_eq!(accounted, actual);
+        assert_eq!(contract.reconcile(&env, Address::random(&env)), Err(LoanError::Unauthorized));
+
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        assert!(contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += 10 * DAY);
+        assert!(contract.repay_loan(&env, borrower.clone(), 100_000_000, None).is_ok());
+        let (accounted, actual) = contract.reconcile(&env, owner.clone()).unwrap();
+        assert_eq!(accounted, actual);
+
+        mint(&env, &contract, &env.current_contract_address(), 5_000_000);
+        assert_eq!(contract.reconcile(&env, owner.clone()), Ok((accounted, actual + 5_000_000)));
+
+        let insurance_before = contract.pool.insurance_fund;
+        assert_eq!(contract.absorb_surplus(&env, owner.clone()), Ok(5_000_000));
+        assert_eq!(contract.pool.insurance_fund, insurance_before + 5_000_000);
+        let (accounted, actual) = contract.reconcile(&env, owner.clone()).unwrap();
+        assert_eq!(accounted, actual);
+    }
+
+    #[test]
+    fn test_simulate_shock_scales_with_defaults() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let collateral_token = env.register_stellar_asset_contract(owner.clone());
+        apply_change(&env, &mut contract, &owner, ParamChange::CollateralToken(collateral_token.clone()));
+        let secured = Address::random(&env);
+        token::StellarAssetClient::new(&env, &collateral_token).mint(&secured, &1_000_000_000);
+        assert!(contract.request_loan(&env, secured.clone(), 100_000_000, TERM, 150_000_000, None).is_ok());
+        assert!(contract.request_loan(&env, Address::random(&env), 100_000_000, TERM, 0, None).is_ok());
+        assert!(contract.request_loan(&env, Address::random(&env), 100_000_000, TERM, 0, None).is_ok());
+        mint(&env, &contract, &owner, 1_000_000_000);
+        assert!(contract.contribute_to_insurance(&env, owner.clone(), 1_000_000_000).is_ok());
+
+        let funds = contract.pool.total_funds;
+        let insurance = contract.pool.insurance_fund;
+        let (mild, mild_breaches) = contract.simulate_shock(&env, 500, 0);
+        let (severe, _) = contract.simulate_shock(&env, 3_000, 0);
+        assert!(severe > mild && mild > 0);
+        assert_eq!(mild_breaches, 2);
+        // A big enough rate jump pushes the secured loan past its collateral too.
+        assert_eq!(contract.simulate_shock(&env, 500, 2_000).1, 3);
+        assert_eq!(contract.pool.total_funds, funds);
+        assert_eq!(contract.pool.insurance_fund, insurance);
+    }
+
+    #[test]
+    fn test_payment_application_order() {
+        let env = Env::default();
+        let (mut interest_first, _) = setup(&env);
+        let (mut principal_first, owner) = setup(&env);
+        assert!(principal_first.set_payment_application(&env, owner.clone(), PaymentApplication::PrincipalFirst).is_ok());
+        let borrower = Address::random(&env);
+        for contract in [&mut interest_first, &mut principal_first] {
+            mint(&env, contract, &borrower, 1_000_000_000);
+            assert!(contract.request_loan(&env, borrower.clone(), 500_000_000, TERM, 0, None).is_ok());
+        }
+        env.ledger().with_mut(|li| li.timestamp += 30 * DAY);
+
+        // The payment is smaller than the month's interest.
+        let (principal, interest, _, _, _) = interest_first.preview_repayment(&env, 1, 1_000_000).unwrap();
+        assert_eq!((principal, interest), (0, 1_000_000));
+        assert!(interest_first.repay_loan(&env, borrower.clone(), 1_000_000, None).is_ok());
+        let loan = interest_first.get_loan_by_id(1).unwrap();
+        assert_eq!(loan.interest_paid, 1_000_000);
+        assert!(loan.accrued_interest > loan.interest_paid);
+
+        let (principal, interest, _, _, _) = principal_first.preview_repayment(&env, 1, 1_000_000).unwrap();
+        assert_eq!((principal, interest), (1_000_000, 0));
+        assert!(principal_first.repay_loan(&env, borrower.clone(), 1_000_000, None).is_ok());
+        let loan = principal_first.get_loan_by_id(1).unwrap();
+        assert_eq!(loan.interest_paid, 0);
+        assert_eq!(MicroLoanContract::principal_outstanding(&loan), 499_000_000);
+    }
+
+    #[test]
+    fn test_config_history_records_changes() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let lender = Address::random(&env);
+        mint(&env, &contract, &lender, 1_000_000_000);
+        assert!(contract.deposit(&env, lender.clone(), 1_000_000_000).is_ok());
+        let old_fee = contract.pool.origination_fee_bps;
+
+        let proposal = contract.propose_base_rate(&env, lender.clone(), 700).unwrap();
+        assert!(contract.vote(&env, lender.clone(), proposal, true).is_ok());
+        env.ledger().with_mut(|li| li.timestamp += VOTING_PERIOD_SECS);
+        assert_eq!(contract.finalize_proposal(&env, proposal), Ok(true));
+        let rate_ts = env.ledger().timestamp();
+        apply_change(&env, &mut contract, &owner, ParamChange::OriginationFeeBps(250));
+
+        let history = contract.get_config_history();
+        assert_eq!(history.len(), 2);
+        let rate = history.get(0).unwrap();
+        assert_eq!(rate.ts, rate_ts);
+        assert_eq!(rate.param, Symbol::new(&env, "base_interest_rate"));
+        assert_eq!(rate.old, ConfigValue::Num(500));
+        assert_eq!(rate.new, ConfigValue::Num(700));
+        assert_eq!(rate.by, env.current_contract_address());
+        let fee = history.get(1).unwrap();
+        assert_eq!(fee.ts, rate_ts + PARAM_TIMELOCK_SECS);
+        assert_eq!(fee.param, Symbol::new(&env, "origination_fee_bps"));
+        assert_eq!(fee.old, ConfigValue::Num(old_fee as i128));
+        assert_eq!(fee.new, ConfigValue::Num(250));
+        assert_eq!(fee.by, owner);
+    }
+
+    #[test]
+    fn test_claim_better_rate_only_lowers() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let whale = Address::random(&env);
+        let borrower = Address::random(&env);
+        let fixed_borrower = Address::random(&env);
+        let fixed_id = contract.request_loan(&env, fixed_borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        assert!(contract.set_variable_rate_loans(&env, owner.clone(), true).is_ok());
+        assert!(contract.request_loan(&env, whale, 1_000_000_000, TERM, 0, None).is_ok());
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        let rate = contract.get_loan_by_id(loan_id).unwrap().interest_rate;
+        assert_eq!(contract.claim_better_rate(&env, borrower.clone(), loan_id), Err(LoanError::RateNotImproved));
+
+        let lender = Address::random(&env);
+        mint(&env, &contract, &lender, 10_000_000_000);
+        assert!(contract.deposit(&env, lender.clone(), 10_000_000_000).is_ok());
+        let delta = contract.claim_better_rate(&env, borrower.clone(), loan_id).unwrap();
+        assert!(delta > 0.0);
+        let lowered = contract.get_loan_by_id(loan_id).unwrap().interest_rate;
+        assert_eq!(lowered, rate - delta);
+        assert_eq!(contract.claim_better_rate(&env, Address::random(&env), loan_id), Err(LoanError::LoanNotFound));
+        assert_eq!(contract.claim_better_rate(&env, fixed_borrower.clone(), fixed_id), Err(LoanError::LoanNotFound));
+
+        assert!(contract.withdraw(&env, lender, 10_000_000_000).is_ok());
+        assert_eq!(contract.claim_better_rate(&env, borrower.clone(), loan_id), Err(LoanError::RateNotImproved));
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().interest_rate, lowered);
+    }
+
+    #[test]
+    fn test_force_repay_from_collateral() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let collateral_token = env.register_stellar_asset_contract(owner.clone());
+        apply_change(&env, &mut contract, &owner, ParamChange::CollateralToken(collateral_token.clone()));
+        let borrower = Address::random(&env);
+        token::StellarAssetClient::new(&env, &collateral_token).mint(&borrower, &125_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 125_000_000, None).unwrap();
+        mint(&env, &contract, &owner, 100_000_000);
+        assert_eq!(contract.force_repay_from_collateral(&env, owner.clone(), loan_id, 40_000_000), Err(LoanError::LoanCurrent));
+
+        env.ledger().with_mut(|li| li.timestamp += INSTALLMENT_PERIOD_SECS + contract.pool.grace_period_secs + 1);
+        let now = env.ledger().timestamp();
+        let before = contract.remaining_balance(&contract.get_loan_by_id(loan_id).unwrap(), now);
+        assert_eq!(
+            contract.force_repay_from_collateral(&env, owner.clone(), loan_id, 125_000_001),
+            Err(LoanError::InsufficientCollateral)
+        );
+        assert_eq!(contract.force_repay_from_collateral(&env, owner.clone(), loan_id, 40_000_000), Ok(40_000_000));
+
+        let loan = contract.get_loan_by_id(loan_id).unwrap();
+        assert_eq!(loan.collateral, 85_000_000);
+        assert_eq!(contract.remaining_balance(&loan, now), before - 40_000_000);
+        assert_eq!(loan.status, LoanStatus::Active);
+        assert_eq!(token::Client::new(&env, &collateral_token).balance(&owner), 40_000_000);
+
+        // Past maturity, once the interest is paid, collateral covering the
+        // rest of the principal closes the loan and frees what's left.
+        env.ledger().with_mut(|li| li.timestamp += TERM);
+        let now = env.ledger().timestamp();
+        assert!(contract.set_min_repayment(&env, owner.clone(), 1).is_ok());
+        let interest = contract.remaining_balance(&loan, now) - MicroLoanContract::principal_outstanding(&loan);
+        assert!(contract.repay_loan(&env, borrower.clone(), interest, None).is_ok());
+        // That payment already released the 10M held beyond the LTV need.
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().collateral, 75_000_000);
+        assert_eq!(contract.force_repay_from_collateral(&env, owner.clone(), loan_id, 75_000_000), Ok(60_000_000));
+        let loan = contract.get_loan_by_id(loan_id).unwrap();
+        assert_eq!(loan.status, LoanStatus::Repaid);
+        assert_eq!(loan.collateral, 0);
+        assert_eq!(token::Client::new(&env, &collateral_token).balance(&borrower), 25_000_000);
+        assert_eq!(contract.pool.repaid_counts.get(borrower.clone()), Some(1));
+    }
+
+    #[test]
+    fn test_version_and_capabilities() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert_eq!(contract.version(), 1);
+        assert_eq!(contract.capabilities(), CAP_INSURANCE | CAP_GOVERNANCE);
+
+        let collateral_token = env.register_stellar_asset_contract(owner.clone());
+        apply_change(&env, &mut contract, &owner, ParamChange::CollateralToken(collateral_token));
+        let blend = env.register_contract(None, MockBlend);
+        assert!(contract.set_blend_pool(&env, owner.clone(), Some(blend)).is_ok());
+        assert!(contract.set_insurance_funding_bps(&env, owner.clone(), 0).is_ok());
+        assert_eq!(contract.capabilities(), CAP_COLLATERAL | CAP_BLEND | CAP_GOVERNANCE);
+    }
+
+    #[test]
+    fn test_max_active_loans() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert_eq!(contract.set_max_active_loans(&env, owner.clone(), 0), Err(LoanError::InvalidParameter));
+        assert!(contract.set_max_active_loans(&env, owner.clone(), 2).is_ok());
+        let first = Address::random(&env);
+        let first_id = contract.request_loan(&env, first.clone(), 100_000_000, TERM, 0, None).unwrap();
+        assert!(contract.request_loan(&env, Address::random(&env), 100_000_000, TERM, 0, None).is_ok());
+        let third = Address::random(&env);
+        assert_eq!(contract.request_loan(&env, third.clone(), 100_000_000, TERM, 0, None), Err(LoanError::ActiveLoanCapReached));
+
+        let payoff = contract.estimate_payoff_at(&env, first_id, env.ledger().timestamp()).unwrap();
+        mint(&env, &contract, &first, payoff);
+        assert!(contract.repay_loan(&env, first, payoff, None).is_ok());
+        assert_eq!(contract.get_loan_by_id(first_id).unwrap().status, LoanStatus::Repaid);
+        assert!(contract.request_loan(&env, third, 100_000_000, TERM, 0, None).is_ok());
+    }
+
+    #[test]
+    fn test_initialize_validates_and_escrows() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let owner = Address::random(&env);
+        let token = env.register_stellar_asset_contract(owner.clone());
+        token::StellarAssetClient::new(&env, &token).mint(&owner, &1_000_000_000);
+        let collector = Address::random(&env);
+        for funds in [0, -1] {
+            let result = MicroLoanContract::initialize(&env, owner.clone(), token.clone(), collector.clone(), funds, 5.0);
+            assert_eq!(result.err(), Some(LoanError::InvalidAmount));
+        }
+        let result = MicroLoanContract::initialize(&env, owner.clone(), token.clone(), collector.clone(), 1_000_000_000, -0.5);
+        assert_eq!(result.err(), Some(LoanError::InvalidParameter));
+
+        let contract = MicroLoanContract::initialize(&env, owner.clone(), token.clone(), collector, 1_000_000_000, 0.0).unwrap();
+        let client = token::Client::new(&env, &token);
+        assert_eq!(client.balance(&owner), 0);
+        assert_eq!(client.balance(&env.current_contract_address()), 1_000_000_000);
+        assert_eq!(contract.pool.total_funds, 1_000_000_000);
+        assert_eq!(contract.pool.insurance_fund, 0);
+    }
+
+    #[test]
+    fn test_early_repayment_penalty() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert_eq!(contract.set_early_repayment_terms(&env, owner.clone(), 100, 200), Err(LoanError::InvalidParameter));
+        assert!(contract.set_early_repayment_terms(&env, owner.clone(), 0, 200).is_ok());
+        let borrower = Address::random(&env);
+        mint(&env, &contract, &borrower, 1_000_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        env.ledger().with_mut(|li| li.timestamp += 10 * DAY);
+
+        // Nothing is due yet, so a partial prepayment is charged 2% of the
+        // principal it covers on top.
+        let client = token::Client::new(&env, &contract.pool.token);
+        let yield_before = contract.claimable_yield(owner.clone());
+        let before = client.balance(&borrower);
+        assert!(contract.repay_loan(&env, borrower.clone(), 50_000_000, None).is_ok());
+        let loan = contract.get_loan_by_id(loan_id).unwrap();
+        let penalty = (50_000_000 - loan.interest_paid) * 200 / 10_000;
+        assert!(penalty > 0);
+        assert_eq!(before - client.balance(&borrower), 50_000_000 + penalty);
+        assert!(contract.claimable_yield(owner.clone()) - yield_before >= penalty);
+
+        // Closing the rest out in two steps still pays the penalty on all of it.
+        let now = env.ledger().timestamp();
+        let outstanding = MicroLoanContract::principal_outstanding(&loan);
+        let payoff = contract.estimate_payoff_at(&env, loan_id, now).unwrap();
+        let before = client.balance(&borrower);
+        assert!(contract.repay_loan(&env, borrower.clone(), payoff - 1, None).is_ok());
+        assert!(contract.repay_loan(&env, borrower.clone(), 1, None).is_ok());
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().status, LoanStatus::Repaid);
+        // Give or take a stroop of rounding across the two payments.
+        assert!(before - client.balance(&borrower) >= payoff + outstanding * 200 / 10_000 - 1);
+    }
+
+    #[test]
+    fn test_aging_report_buckets() {
+        let env = Env::default();
+        let (mut contract, _owner) = setup(&env);
+        // Each loan's first installment falls due 30 days after it opens.
+        let mut ids = Vec::new(&env);
+        for opened_at in [0, 20 * DAY, 45 * DAY, 90 * DAY] {
+            env.ledger().with_mut(|li| li.timestamp = opened_at);
+            ids.push_back(contract.request_loan(&env, Address::random(&env), 100_000_000, TERM, 0, None).unwrap());
+        }
+        env.ledger().with_mut(|li| li.timestamp = 100 * DAY);
+
+        assert_eq!(contract.loan_age_secs(&env, ids.get(0).unwrap()), Ok(100 * DAY));
+        assert_eq!(contract.loan_age_secs(&env, ids.get(3).unwrap()), Ok(10 * DAY));
+        assert_eq!(contract.loan_age_secs(&env, 999), Err(LoanError::LoanNotFound));
+        let report = contract.aging_report(&env);
+        assert_eq!(report.len(), 4);
+        assert_eq!(report.get(0), Some((0, 1)));
+        assert_eq!(report.get(1), Some((1, 1)));
+        assert_eq!(report.get(2), Some((31, 1)));
+        assert_eq!(report.get(3), Some((61, 1)));
+    }
+
+    #[test]
+    fn test_set_base_rate_reprices_variable_loans() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let fixed_id = contract.request_loan(&env, Address::random(&env), 100_000_000, TERM, 0, None).unwrap();
+        assert!(contract.set_variable_rate_loans(&env, owner.clone(), true).is_ok());
+        let small = contract.request_loan(&env, Address::random(&env), 100_000_000, TERM, 0, None).unwrap();
+        let large = contract.request_loan(&env, Address::random(&env), 200_000_000, TERM, 0, None).unwrap();
+        // Still waiting out a disbursement delay, but repriced all the same.
+        assert!(contract.set_disbursement_delay_secs(&env, owner.clone(), DAY).is_ok());
+        let waiting = contract.request_loan(&env, Address::random(&env), 100_000_000, TERM, 0, None).unwrap();
+        let variable_ids = [small, large, waiting];
+        assert_eq!(contract.get_loan_by_id(variable_ids[2]).unwrap().status, LoanStatus::ReadyToDisburse);
+        let rate = |contract: &MicroLoanContract, id: u64| contract.get_loan_by_id(id).unwrap().interest_rate;
+        let fixed_before = rate(&contract, fixed_id);
+        let variable_before = [rate(&contract, variable_ids[0]), rate(&contract, variable_ids[1]), rate(&contract, variable_ids[2])];
+
+        assert_eq!(contract.set_base_rate(&env, Address::random(&env), 610), Err(LoanError::Unauthorized));
+        assert!(contract.set_base_rate(&env, owner.clone(), 610).is_ok());
+        assert_eq!(contract.get_config().base_interest_rate, 6.1);
+        assert_eq!(rate(&contract, fixed_id), fixed_before);
+        // 6.1% is recorded as 610 bps, not truncated to 609.
+        assert!(contract.set_base_rate(&env, owner.clone(), 600).is_ok());
+        let history = contract.get_config_history();
+        assert_eq!(history.get(history.len() - 1).unwrap().old, ConfigValue::Num(610));
+        for (id, before) in variable_ids.iter().zip(variable_before.iter()) {
+            assert!((rate(&contract, *id) - before - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_moved_shares_cannot_vote_twice() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let lender = Address::random(&env);
+        let other = Address::random(&env);
+        mint(&env, &contract, &lender, 1_000_000_000);
+        assert!(contract.deposit(&env, lender.clone(), 1_000_000_000).is_ok());
+        let proposal = contract.propose_base_rate(&env, owner.clone(), 700).unwrap();
+
+        assert!(contract.vote(&env, lender.clone(), proposal, true).is_ok());
+        assert!(contract.transfer_shares(&env, lender.clone(), other.clone(), 1_000_000_000).is_ok());
+        assert_eq!(contract.vote(&env, other.clone(), proposal, true), Err(LoanError::InsufficientShares));
+        assert_eq!(contract.pool.rate_proposals.get(proposal).unwrap().votes_for, 1_000_000_000);
+
+        // Shares received before a proposal opens do count on it.
+        let next = contract.propose_base_rate(&env, other.clone(), 800).unwrap();
+        assert!(contract.vote(&env, other.clone(), next, true).is_ok());
+        assert_eq!(contract.vote(&env, lender, next, true), Err(LoanError::InsufficientShares));
+    }
+
+    #[test]
+    fn test_transfer_loan_checks_new_borrower() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let from = Address::random(&env);
+        let loan_id = contract.request_loan(&env, from.clone(), 100_000_000, TERM, 0, None).unwrap();
+
+        let defaulter = Address::random(&env);
+        assert!(contract.set_default_cooldown_secs(&env, owner.clone(), 90 * DAY).is_ok());
+        contract.pool.defaulted_at.set(defaulter.clone(), env.ledger().timestamp());
+        assert_eq!(contract.transfer_loan(&env, from.clone(), defaulter, loan_id, false), Err(LoanError::DefaultCooldownActive));
+
+        let risky = Address::random(&env);
+        assert!(contract.set_credit_score(&env, owner.clone(), risky.clone(), 400).is_ok());
+        assert!(contract.set_band_lending(&env, owner.clone(), 0, false).is_ok());
+        assert_eq!(contract.transfer_loan(&env, from.clone(), risky, loan_id, false), Err(LoanError::BandLendingPaused));
+        assert!(contract.set_band_lending(&env, owner.clone(), 0, true).is_ok());
+
+        let recent = Address::random(&env);
+        assert!(contract.set_cooldown_secs(&env, owner.clone(), DAY).is_ok());
+        assert!(contract.request_loan(&env, recent.clone(), 100_000_000, TERM, 0, None).is_ok());
+        assert_eq!(contract.transfer_loan(&env, from.clone(), recent, loan_id, false), Err(LoanError::BorrowerInCooldown));
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().borrower, from);
+    }
+
+    #[test]
+    fn test_withdraw_keeps_reserve() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        apply_change(&env, &mut contract, &owner, ParamChange::ReserveBps(1_000));
+        assert_eq!(contract.withdraw(&env, owner.clone(), 9_500_000_000), Err(LoanError::InsufficientFunds));
+        assert_eq!(contract.withdraw(&env, owner.clone(), 9_000_000_000), Ok(9_000_000_000));
+        assert_eq!(contract.pool.total_funds, 1_000_000_000);
+    }
+
+    #[test]
+    fn test_frequent_batch_accrue_books_same_interest() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let keeper = Address::random(&env);
+        assert!(contract.set_keeper(&env, owner.clone(), Some(keeper.clone())).is_ok());
+        let small = contract.request_loan(&env, Address::random(&env), 10_000_000, TERM, 0, None).unwrap();
+        let large = contract.request_loan(&env, Address::random(&env), 900_000_000, TERM, 0, None).unwrap();
+        let start = env.ledger().timestamp();
+        let span = 2 * 60 * 60;
+        let single_small = contract.pending_interest(&contract.get_loan_by_id(small).unwrap(), start + span);
+        let single_large = contract.pending_interest(&contract.get_loan_by_id(large).unwrap(), start + span);
+        assert!(single_small > 0);
+
+        // Every 5-second step books well under a stroop on the small loan.
+        for step in 1..=(span / 5) {
+            env.ledger().with_mut(|li| li.timestamp = start + step * 5);
+            assert_eq!(contract.batch_accrue(&env, keeper.clone(), 0, 10), Ok(2));
+        }
+        assert_eq!(contract.get_loan_by_id(small).unwrap().accrued_interest, single_small);
+        assert_eq!(contract.get_loan_by_id(large).unwrap().accrued_interest, single_large);
+    }
+
+    #[test]
+    fn test_mid_period_accrual_does_not_compound_early() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_compounding_period_secs(&env, owner.clone(), 30 * DAY).is_ok());
+        let loan_id = contract.request_loan(&env, Address::random(&env), 500_000_000, TERM, 0, None).unwrap();
+        let start = env.ledger().timestamp();
+        let first_period = contract.pending_interest(&contract.get_loan_by_id(loan_id).unwrap(), start + 30 * DAY);
+        let single = contract.pending_interest(&contract.get_loan_by_id(loan_id).unwrap(), start + 60 * DAY);
+
+        env.ledger().with_mut(|li| li.timestamp = start + 15 * DAY);
+        assert_eq!(contract.batch_accrue(&env, owner.clone(), 0, 10), Ok(1));
+        env.ledger().with_mut(|li| li.timestamp = start + 30 * DAY);
+        assert_eq!(contract.batch_accrue(&env, owner.clone(), 0, 10), Ok(1));
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().accrued_interest, first_period);
+        env.ledger().with_mut(|li| li.timestamp = start + 45 * DAY);
+        assert_eq!(contract.batch_accrue(&env, owner.clone(), 0, 10), Ok(1));
+        env.ledger().with_mut(|li| li.timestamp = start + 60 * DAY);
+        assert_eq!(contract.batch_accrue(&env, owner.clone(), 0, 10), Ok(1));
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().accrued_interest, single);
+    }
+
+    #[test]
+    fn test_overdue_loan_gets_no_reward_on_reprice() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        assert!(contract.set_variable_rate_loans(&env, owner.clone(), true).is_ok());
+        let borrower = Address::random(&env);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        let rate = contract.get_loan_by_id(loan_id).unwrap().interest_rate;
+
+        // Savings reach the reward threshold while the first installment is missed.
+        env.ledger().with_mut(|li| li.timestamp += 40 * DAY);
+        let mut loan = contract.pool.loans.get(loan_id).unwrap();
+        loan.savings = contract.pool.reward_threshold;
+        contract.pool.loans.set(loan_id, loan);
+        assert_eq!(contract.claim_better_rate(&env, borrower.clone(), loan_id), Err(LoanError::RateNotImproved));
+
+        assert!(contract.set_base_rate(&env, owner.clone(), 600).is_ok());
+        let repriced = contract.get_loan_by_id(loan_id).unwrap();
+        assert!((repriced.interest_rate - (rate + 1.0)).abs() < 0.001);
+
+        let to = Address::random(&env);
+        assert!(contract.transfer_loan(&env, borrower.clone(), to, loan_id, true).is_ok());
+        let transferred = contract.get_loan_by_id(loan_id).unwrap();
+        assert!(transferred.interest_rate > transferred.base_rate - 0.001);
+    }
+
+    #[test]
+    fn test_cancel_undisbursed_loan_refunds_collateral() {
+        let env = Env::default();
+        let (mut contract, owner) = setup(&env);
+        let collateral_token = env.register_stellar_asset_contract(owner.clone());
+        apply_change(&env, &mut contract, &owner, ParamChange::CollateralToken(collateral_token.clone()));
+        let mut underwriters = Vec::new(&env);
+        underwriters.push_back(Address::random(&env));
+        assert!(contract.set_underwriters(&env, owner.clone(), underwriters, 1, 200_000_000).is_ok());
+        assert!(contract.set_max_loans(&env, owner.clone(), 1).is_ok());
+        let borrower = Address::random(&env);
+        token::StellarAssetClient::new(&env, &collateral_token).mint(&borrower, &100_000_000);
+        let loan_id = contract.request_loan(&env, borrower.clone(), 300_000_000, TERM, 100_000_000, None).unwrap();
+        assert_eq!(contract.get_loan_by_id(loan_id).unwrap().status, LoanStatus::Pending);
+        assert_eq!(contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None), Err(LoanError::LoanCapReached));
+
+        assert_eq!(contract.cancel_loan(&env, Address::random(&env), loan_id).err(), Some(LoanError::Unauthorized));
+        let cancelled = contract.cancel_loan(&env, borrower.clone(), loan_id).unwrap();
+        assert_eq!(cancelled.collateral, 100_000_000);
+        assert!(contract.get_loan_by_id(loan_id).is_none());
+        assert_eq!(token::Client::new(&env, &collateral_token).balance(&borrower), 100_000_000);
+
+        // The slot is free again; disbursed loans can't be cancelled.
+        let active_id = contract.request_loan(&env, borrower.clone(), 100_000_000, TERM, 0, None).unwrap();
+        assert_eq!(contract.cancel_loan(&env, owner.clone(), active_id).err(), Some(LoanError::LoanNotFound));
+    }
+}